@@ -12,35 +12,31 @@ use std::io::{self, BufReader};
 
 use futures::prelude::*;
 use tokio_core::net::{TcpListener, TcpStream};
-use tokio_core::reactor::Core;
+use tokio_core::reactor::Handle;
 use tokio_io::{AsyncRead};
 
-fn main() {
-    // Create the event loop that will drive this server
-    let mut core = Core::new().unwrap();
-    let handle = core.handle();
-
+// The event loop that will drive this server is created for us, and its
+// handle passed in.
+#[async_main(tokio)]
+fn main(handle: Handle) -> io::Result<()> {
     // Bind the server's socket
     let addr = "127.0.0.1:12345".parse().unwrap();
-    let tcp = TcpListener::bind(&addr, &handle).expect("failed to bind listener");
+    let tcp = TcpListener::bind(&addr, &handle)?;
     println!("listening for connections on {}",
              tcp.local_addr().unwrap());
 
-    let server = async_block! {
-        #[async]
-        for (client, _) in tcp.incoming() {
-            handle.spawn(handle_client(client).then(|result| {
-                match result {
-                    Ok(n) => println!("wrote {} bytes", n),
-                    Err(e) => println!("IO error {:?}", e),
-                }
-                Ok(())
-            }));
-        }
-
-        Ok::<(), io::Error>(())
-    };
-    core.run(server).unwrap();
+    #[async]
+    for (client, _) in tcp.incoming() {
+        handle.spawn(handle_client(client).then(|result| {
+            match result {
+                Ok(n) => println!("wrote {} bytes", n),
+                Err(e) => println!("IO error {:?}", e),
+            }
+            Ok(())
+        }));
+    }
+
+    Ok(())
 }
 
 #[async]
@@ -0,0 +1,537 @@
+//! Detection of borrows which are held across suspension points.
+//!
+//! Generators today can't hold borrows of their own local variables across a
+//! `yield`, so something like:
+//!
+//!      for line in string.lines() {
+//!          await!(write(line))?;
+//!      }
+//!
+//! fails to compile with a rather cryptic error about generators. This module
+//! performs a (purely syntactic, and so approximate) analysis of the body of an
+//! async function to find these cases and warn about them in terms of
+//! `await!`, pointing at the borrow and the suspension point it's held across.
+//! The compiler's own error is still reported, the warning just explains it.
+//!
+//! The analysis tracks local variables through their scopes, noting those
+//! which were initialized with a borrow of another local (such as `&x` or
+//! `x.iter()`), and reports any of them which are used again after a
+//! suspension point (`await!`, `await_item!`, `stream_yield!` or an
+//! `#[async]` for loop). Anything the analysis can't understand, for example
+//! a borrow of a variable it doesn't know about or a borrow through a
+//! reference, is assumed to be fine.
+
+use std::collections::HashSet;
+
+use proc_macro2::Span;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::*;
+
+/// Methods which are known to return a value borrowing from their receiver.
+const BORROWING_METHODS: &[&str] = &[
+	"as_bytes",
+	"as_mut",
+	"as_ref",
+	"as_slice",
+	"as_str",
+	"borrow",
+	"borrow_mut",
+	"bytes",
+	"char_indices",
+	"chars",
+	"chunks",
+	"drain",
+	"first",
+	"get",
+	"get_mut",
+	"iter",
+	"iter_mut",
+	"keys",
+	"last",
+	"lines",
+	"lock",
+	"split",
+	"split_whitespace",
+	"trim",
+	"values",
+	"values_mut",
+	"windows",
+];
+
+/// Macros which suspend the enclosing generator.
+const SUSPENDING_MACROS: &[&str] = &[
+	"async_scope",
+	"await",
+	"await_blocking",
+	"await_item",
+	"await_poll",
+	"stream_yield",
+	"yield_now",
+];
+
+/// Macros which create a generator of their own, and are checked separately.
+const NESTED_MACROS: &[&str] = &[
+	"async_block",
+	"async_closure",
+	"async_stream_block",
+	"async_stream_closure",
+];
+
+/// Checks the arguments and body of an `#[async]` function.
+pub fn check_fn<'a, I>(inputs: I, block: &Block)
+where
+	I: IntoIterator<Item = &'a FnArg>,
+{
+	let mut analysis = Analysis::default();
+	analysis.scopes.push(Vec::new());
+	for input in inputs {
+		match *input {
+			FnArg::SelfRef(_) => {
+				analysis.bind_ident("self".to_string(), None, true);
+			}
+			FnArg::SelfValue(_) => {
+				analysis.bind_ident("self".to_string(), None, false);
+			}
+			FnArg::Captured(ref arg) => {
+				let is_ref = is_reference(&arg.ty);
+				analysis.bind_pat(&arg.pat, None, is_ref);
+			}
+			_ => {}
+		}
+	}
+	analysis.visit_block(block);
+}
+
+/// Checks the body of an `async_block!` or `async_stream_block!`.
+pub fn check_expr(expr: &Expr) {
+	let mut analysis = Analysis::default();
+	analysis.scopes.push(Vec::new());
+	analysis.visit_expr(expr);
+}
+
+#[derive(Default)]
+struct Analysis {
+	scopes: Vec<Vec<Binding>>,
+	reported: HashSet<usize>,
+}
+
+struct Binding {
+	name: String,
+	// Whether this variable is known to be a reference itself, in which case
+	// calling borrowing methods on it doesn't borrow a local.
+	is_ref: bool,
+	borrow: Option<Borrow>,
+}
+
+#[derive(Clone, Copy)]
+struct Borrow {
+	// Identity of the syntax node which created this borrow, used to only
+	// report each borrow once.
+	origin: usize,
+	span: Span,
+	suspended_at: Option<Span>,
+}
+
+impl Analysis {
+	fn bind_ident(&mut self, name: String, borrow: Option<Borrow>, is_ref: bool) {
+		self.scopes.last_mut().unwrap().push(Binding {
+			name,
+			is_ref,
+			borrow,
+		});
+	}
+
+	fn bind_pat(&mut self, pat: &Pat, borrow: Option<Borrow>, is_ref: bool) {
+		let mut idents = PatIdents(Vec::new());
+		idents.visit_pat(pat);
+		for ident in idents.0 {
+			self.bind_ident(ident.to_string(), borrow, is_ref);
+		}
+	}
+
+	fn bind_pats<'a, I>(&mut self, pats: I)
+	where
+		I: IntoIterator<Item = &'a Pat>,
+	{
+		for pat in pats {
+			self.bind_pat(pat, None, false);
+		}
+	}
+
+	fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+		self.scopes
+			.iter_mut()
+			.rev()
+			.flat_map(|scope| scope.iter_mut().rev())
+			.find(|binding| binding.name == name)
+	}
+
+	/// Records that the generator may suspend at `span`, which any borrows
+	/// alive at this point are now held across.
+	fn suspend(&mut self, span: Span) {
+		for binding in self.scopes.iter_mut().flat_map(|s| s.iter_mut()) {
+			if let Some(ref mut borrow) = binding.borrow {
+				if borrow.suspended_at.is_none() {
+					borrow.suspended_at = Some(span);
+				}
+			}
+		}
+	}
+
+	fn use_ident(&mut self, ident: &Ident) {
+		let name = ident.to_string();
+		let borrow = match self.lookup(&name).and_then(|b| b.borrow) {
+			Some(borrow) => borrow,
+			None => return,
+		};
+		if let Some(suspended_at) = borrow.suspended_at {
+			self.report(
+				&borrow,
+				suspended_at,
+				&format!(
+					"`{}` borrows a local variable and is used after a suspension point",
+					name
+				),
+			);
+		}
+	}
+
+	fn report(&mut self, borrow: &Borrow, suspended_at: Span, msg: &str) {
+		if !self.reported.insert(borrow.origin) {
+			return;
+		}
+		borrow
+			.span
+			.unstable()
+			.warning(msg)
+			.span_note(
+				suspended_at.unstable(),
+				"the borrow is held across this suspension point",
+			)
+			.help(
+				"borrows of local variables can't be held across `await!` or \
+				 `#[async]` for loops, consider cloning the data or restricting \
+				 the borrow to code between suspension points",
+			)
+			.emit();
+	}
+
+	/// Determines whether `expr` borrows a local variable, returning the
+	/// borrow it creates if so.
+	fn borrow_of(&mut self, expr: &Expr, origin: usize) -> Option<Borrow> {
+		let borrow = Borrow {
+			origin,
+			span: expr.span(),
+			suspended_at: None,
+		};
+		match *expr {
+			Expr::Paren(ref e) => self.borrow_of(&e.expr, origin),
+			Expr::Reference(ref e) => {
+				// Borrowing part of what a reference points to, like `&s[..]`,
+				// doesn't borrow the reference itself.
+				let projected = match *e.expr {
+					Expr::Path(_) => false,
+					_ => true,
+				};
+				match self.local_place(&e.expr) {
+					Some(ref binding) if projected && binding.is_ref => None,
+					Some(_) => Some(borrow),
+					None => None,
+				}
+			}
+			Expr::MethodCall(ref e) => {
+				let method = e.method.to_string();
+				if !BORROWING_METHODS.contains(&&*method) {
+					return None;
+				}
+				match self.local_place(&e.receiver) {
+					Some(binding) if !binding.is_ref => Some(borrow),
+					_ => None,
+				}
+			}
+			// Copying a reference to a local copies its borrow
+			Expr::Path(ref e) => match single_ident(e) {
+				Some(ident) => self
+					.lookup(&ident.to_string())
+					.and_then(|b| b.borrow)
+					.map(|b| Borrow { origin, ..b }),
+				None => None,
+			},
+			_ => None,
+		}
+	}
+
+	/// Returns the local variable at the root of a place expression like
+	/// `a.b[c]`, if it's one we know about.
+	fn local_place(&mut self, expr: &Expr) -> Option<&mut Binding> {
+		match *expr {
+			Expr::Paren(ref e) => self.local_place(&e.expr),
+			Expr::Field(ref e) => self.local_place(&e.base),
+			Expr::Index(ref e) => self.local_place(&e.expr),
+			Expr::Unary(ExprUnary { op: UnOp::Deref(_), ref expr, .. }) => self.local_place(expr),
+			Expr::Path(ref e) => match single_ident(e) {
+				Some(ident) => self.lookup(&ident.to_string()),
+				None => None,
+			},
+			_ => None,
+		}
+	}
+
+	/// Visits the body of a loop, which may be executed any number of times.
+	///
+	/// The body is visited twice so uses at the top of the loop are checked
+	/// against suspension points at the bottom of the previous iteration.
+	fn visit_loop_body<F>(&mut self, mut f: F)
+	where
+		F: FnMut(&mut Analysis),
+	{
+		f(self);
+		f(self);
+	}
+}
+
+impl<'ast> Visit<'ast> for Analysis {
+	fn visit_block(&mut self, block: &'ast Block) {
+		self.scopes.push(Vec::new());
+		visit::visit_block(self, block);
+		self.scopes.pop();
+	}
+
+	fn visit_local(&mut self, local: &'ast Local) {
+		let mut borrow = None;
+		if let Some((_, ref init)) = local.init {
+			self.visit_expr(init);
+			borrow = self.borrow_of(init, local as *const Local as usize);
+		}
+		let is_ref = match local.ty {
+			Some((_, ref ty)) => is_reference(ty),
+			None => match local.init {
+				Some((_, ref init)) => match **init {
+					Expr::Lit(_) => true,
+					_ => false,
+				},
+				None => false,
+			},
+		};
+		for pat in local.pats.iter() {
+			self.bind_pat(pat, borrow, is_ref);
+		}
+	}
+
+	fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+		match *stmt {
+			Stmt::Item(Item::Macro(ref item)) => self.visit_macro(&item.mac),
+			Stmt::Item(_) => {}
+			_ => visit::visit_stmt(self, stmt),
+		}
+	}
+
+	fn visit_expr_path(&mut self, path: &'ast ExprPath) {
+		if let Some(ident) = single_ident(path) {
+			self.use_ident(ident);
+		}
+	}
+
+	fn visit_macro(&mut self, mac: &'ast Macro) {
+		let name = mac.path.segments.last().unwrap().value().ident.to_string();
+		if NESTED_MACROS.contains(&&*name) {
+			return;
+		}
+
+		// Visit the arguments to the macro if we can make sense of them, the
+		// suspension itself happens after they've been evaluated.
+		let args = syn::parse2::<MacroArgs>(mac.tts.clone()).ok();
+		if let Some(args) = args {
+			for arg in args.0.iter() {
+				Visit::visit_expr(self, arg);
+			}
+		}
+
+		if mac.path.segments.len() == 1 && SUSPENDING_MACROS.contains(&&*name) {
+			self.suspend(mac.span());
+		}
+	}
+
+	fn visit_expr_yield(&mut self, expr: &'ast ExprYield) {
+		visit::visit_expr_yield(self, expr);
+		self.suspend(expr.span());
+	}
+
+	fn visit_expr_for_loop(&mut self, expr: &'ast ExprForLoop) {
+		self.visit_expr(&expr.expr);
+
+		// The iterator (or stream) of a `for` loop is alive for the whole
+		// loop, so if it borrows a local no suspension points can happen in
+		// the body. An `#[async]` for loop suspends itself on each iteration.
+		let is_async = is_async_for(expr);
+		let iter = self.borrow_of(&expr.expr, expr as *const ExprForLoop as usize);
+		if let Some(ref iter) = iter {
+			let suspended_at = if is_async {
+				Some(expr.for_token.span())
+			} else {
+				first_suspension(&expr.body)
+			};
+			if let Some(suspended_at) = suspended_at {
+				self.report(
+					iter,
+					suspended_at,
+					"this loop borrows a local variable and is held across a \
+					 suspension point",
+				);
+			}
+		}
+
+		self.visit_loop_body(|me| {
+			if is_async {
+				me.suspend(expr.for_token.span());
+			}
+			me.scopes.push(Vec::new());
+			me.bind_pat(&expr.pat, iter, false);
+			me.visit_block(&expr.body);
+			me.scopes.pop();
+		});
+	}
+
+	fn visit_expr_loop(&mut self, expr: &'ast ExprLoop) {
+		self.visit_loop_body(|me| me.visit_block(&expr.body));
+	}
+
+	fn visit_expr_while(&mut self, expr: &'ast ExprWhile) {
+		self.visit_loop_body(|me| {
+			me.visit_expr(&expr.cond);
+			me.visit_block(&expr.body);
+		});
+	}
+
+	fn visit_expr_while_let(&mut self, expr: &'ast ExprWhileLet) {
+		self.visit_loop_body(|me| {
+			me.visit_expr(&expr.expr);
+			me.scopes.push(Vec::new());
+			me.bind_pats(&expr.pats);
+			me.visit_block(&expr.body);
+			me.scopes.pop();
+		});
+	}
+
+	fn visit_expr_if_let(&mut self, expr: &'ast ExprIfLet) {
+		self.visit_expr(&expr.expr);
+		self.scopes.push(Vec::new());
+		self.bind_pats(&expr.pats);
+		self.visit_block(&expr.then_branch);
+		self.scopes.pop();
+		if let Some((_, ref else_branch)) = expr.else_branch {
+			self.visit_expr(else_branch);
+		}
+	}
+
+	fn visit_arm(&mut self, arm: &'ast Arm) {
+		self.scopes.push(Vec::new());
+		self.bind_pats(&arm.pats);
+		if let Some((_, ref guard)) = arm.guard {
+			self.visit_expr(guard);
+		}
+		self.visit_expr(&arm.body);
+		self.scopes.pop();
+	}
+
+	fn visit_expr_closure(&mut self, expr: &'ast ExprClosure) {
+		self.scopes.push(Vec::new());
+		for input in expr.inputs.iter() {
+			match *input {
+				FnArg::Captured(ref arg) => self.bind_pat(&arg.pat, None, false),
+				FnArg::Inferred(ref pat) => self.bind_pat(pat, None, false),
+				_ => {}
+			}
+		}
+		self.visit_expr(&expr.body);
+		self.scopes.pop();
+	}
+
+	// Don't recurse into items
+	fn visit_item(&mut self, _item: &'ast Item) {}
+}
+
+/// Returns the first suspension point inside of `block`, if any.
+fn first_suspension(block: &Block) -> Option<Span> {
+	let mut points = SuspensionPoints(None);
+	points.visit_block(block);
+	points.0
+}
+
+fn is_async_for(expr: &ExprForLoop) -> bool {
+	expr.attrs.iter().any(|attr| {
+		attr.path.segments.len() == 1 && attr.path.segments[0].ident == "async"
+	})
+}
+
+struct SuspensionPoints(Option<Span>);
+
+impl<'ast> Visit<'ast> for SuspensionPoints {
+	fn visit_macro(&mut self, mac: &'ast Macro) {
+		let name = mac.path.segments.last().unwrap().value().ident.to_string();
+		if self.0.is_none() && mac.path.segments.len() == 1 && SUSPENDING_MACROS.contains(&&*name) {
+			self.0 = Some(mac.span());
+		}
+	}
+
+	fn visit_expr_for_loop(&mut self, expr: &'ast ExprForLoop) {
+		if self.0.is_none() && is_async_for(expr) {
+			self.0 = Some(expr.for_token.span());
+		}
+		visit::visit_expr_for_loop(self, expr);
+	}
+
+	fn visit_expr_yield(&mut self, expr: &'ast ExprYield) {
+		if self.0.is_none() {
+			self.0 = Some(expr.span());
+		}
+	}
+
+	fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+		match *stmt {
+			Stmt::Item(Item::Macro(ref item)) => self.visit_macro(&item.mac),
+			_ => visit::visit_stmt(self, stmt),
+		}
+	}
+
+	fn visit_expr_closure(&mut self, _expr: &'ast ExprClosure) {}
+
+	fn visit_item(&mut self, _item: &'ast Item) {}
+}
+
+struct PatIdents(Vec<Ident>);
+
+impl<'ast> Visit<'ast> for PatIdents {
+	fn visit_pat_ident(&mut self, pat: &'ast PatIdent) {
+		self.0.push(pat.ident.clone());
+		visit::visit_pat_ident(self, pat);
+	}
+}
+
+/// The arguments of a macro invocation which looks like a function call.
+struct MacroArgs(Punctuated<Expr, Token![,]>);
+
+impl synom::Synom for MacroArgs {
+	named!(parse -> Self, map!(
+        call!(Punctuated::parse_terminated),
+        MacroArgs
+    ));
+}
+
+fn single_ident(path: &ExprPath) -> Option<&Ident> {
+	if path.qself.is_none() && path.path.leading_colon.is_none() && path.path.segments.len() == 1 {
+		Some(&path.path.segments[0].ident)
+	} else {
+		None
+	}
+}
+
+fn is_reference(ty: &Type) -> bool {
+	match *ty {
+		Type::Reference(_) => true,
+		Type::Paren(ref ty) => is_reference(&ty.elem),
+		_ => false,
+	}
+}
@@ -9,7 +9,7 @@
 //! Currently this crate depends on `syn` and `quote` to do all the heavy
 //! lifting, this is just a very small shim around creating a closure/future out
 //! of a generator.
-#![feature(proc_macro)]
+#![feature(proc_macro, proc_macro_diagnostic)]
 #![recursion_limit = "128"]
 
 extern crate proc_macro;
@@ -20,8 +20,9 @@ extern crate quote;
 extern crate syn;
 
 use proc_macro::{Delimiter, TokenStream, TokenTree};
-use proc_macro2::{Span, TokenStream as Tokens};
+use proc_macro2::{Literal, Span, TokenStream as Tokens};
 use quote::ToTokens;
+use std::mem;
 use syn::fold::Fold;
 use syn::punctuated::Punctuated;
 use syn::*;
@@ -30,10 +31,15 @@ macro_rules! quote_cs {
     ($($t:tt)*) => (quote_spanned!(Span::call_site() => $($t)*))
 }
 
+mod borrows;
+mod lint;
+
 fn async_inner<F>(
-	boxed: bool,
+	wrappers: Vec<Wrapper>,
 	function: TokenStream,
 	gen_function: Tokens,
+	stream: bool,
+	options: AsyncOptions,
 	return_ty: F,
 ) -> TokenStream
 where
@@ -77,6 +83,8 @@ where
 		),
 	};
 
+	borrows::check_fn(&inputs, &block);
+
 	// We've got to get a bit creative with our handling of arguments. For a
 	// number of reasons we translate this:
 	//
@@ -164,7 +172,10 @@ where
 	//      }
 	//
 	// Basically just take all those expression and expand them.
-	let block = ExpandAsyncFor.fold_block(*block);
+	let block = lint::blocking_calls(*block, &options.blocking_calls);
+	let cancellable = options.cancellable;
+	let block = ExpandAsyncFor { cancellable }.fold_block(block);
+	let block = MarkAsyncContext::new(stream, cancellable).fold_block(block);
 
 	let return_ty = return_ty(&output);
 
@@ -178,7 +189,9 @@ where
 	});
 	syn::token::Semi([block.brace_token.0]).to_tokens(&mut result);
 
+	let await_context = await_context();
 	let gen_body_inner = quote_cs! {
+			#await_context
 			let __e: #output = #result
 
 			// Ensure that this closure is a generator, even if it doesn't
@@ -199,22 +212,75 @@ where
 	// sure if more errors will highlight this function call...
 	let output_span = first_last(&output);
 	let gen_function = respan(gen_function.into(), &output_span);
+	// Futures are told which function they came from so they can name it in
+	// diagnostics, for example if they're polled after they've completed.
+	let name = if stream {
+		format!("#[async_stream] fn {}", ident)
+	} else {
+		format!("#[async] fn {}", ident)
+	};
+	let name = LitStr::new(&name, Span::call_site());
+	let instrument = LitBool { value: options.instrument, span: Span::call_site() };
+	let budget = match options.budget {
+		Some(n) => {
+			let n = Literal::u32_unsuffixed(n as u32);
+			quote_cs! { ::futures::__rt::std::option::Option::Some(#n) }
+		}
+		None => quote_cs! { ::futures::__rt::std::option::Option::None },
+	};
 	let body_inner = quote_cs! {
-			#gen_function (move || -> #output #gen_body)
+			#gen_function (
+					&::futures::__rt::Meta { name: #name, instrument: #instrument, budget: #budget },
+					move || -> #output #gen_body
+			)
 	};
-	let body_inner = if boxed {
-		let body = quote_cs! { ::futures::__rt::std::boxed::Box::new(#body_inner) };
-		respan(body.into(), &output_span)
+	let mut body_inner: proc_macro2::TokenStream = body_inner.into();
+	for wrapper in wrappers {
+		let body = match wrapper {
+			Wrapper::Call(function) => quote_cs! { #function(#body_inner) },
+			Wrapper::Watchdog(millis) => {
+				let millis = Literal::u64_unsuffixed(millis);
+				quote_cs! {
+						::futures::watchdog::Watchdog::new(
+								#body_inner,
+								#name,
+								::futures::__rt::std::time::Duration::from_millis(#millis),
+						)
+				}
+			}
+		};
+		body_inner = respan(body.into(), &output_span);
+	}
+	// Cancellable functions share a flag between the generator, where it's
+	// checked by `await!`, and the handle returned to the caller.
+	let body_inner = if cancellable {
+		quote_cs! {
+				let (__cancel_handle, __cancel) = ::futures::__rt::cancel_pair();
+				(#body_inner, __cancel_handle)
+		}.into()
 	} else {
-		body_inner.into()
+		body_inner
 	};
 	let mut body = Tokens::empty();
 	block.brace_token.surround(&mut body, |tokens| {
 		body_inner.to_tokens(tokens);
 	});
 
+	// Calling an async function doesn't actually run any of its code, so make
+	// sure the returned future isn't accidentally ignored.
+	let must_use = if attrs.iter().any(|attr| attr.path.segments.len() == 1 &&
+		attr.path.segments[0].ident == "must_use")
+	{
+		None
+	} else {
+		let msg = format!("`{}` does nothing unless the value it returns is polled", ident);
+		let msg = LitStr::new(&msg, Span::call_site());
+		Some(quote_cs! { #[must_use = #msg] })
+	};
+
 	let output = quote_cs! {
 			#(#attrs)*
+			#must_use
 			#vis #unsafety #abi #constness
 			#fn_token #ident #generics(#(#inputs_no_patterns),*)
 					#rarrow_token #return_ty
@@ -229,74 +295,395 @@ where
 #[proc_macro_attribute]
 pub fn async(attribute: TokenStream, function: TokenStream) -> TokenStream {
 	// Handle arguments to the #[async] attribute, if any
-	let (boxed, send) = match &attribute.to_string() as &str {
-		"boxed" => (true, false),
-		"boxed_send" => (true, true),
-		"" => (false, false),
-		_ => panic!("the #[async] attribute currently only takes `boxed` as an arg"),
+	let args = syn::parse::<AsyncArgs>(attribute.clone())
+		.expect("failed to parse attribute arguments");
+
+	let mut boxed = false;
+	let mut send = false;
+	let mut inline = None;
+	let mut catch_unwind = false;
+	let mut cancellable = false;
+	let mut instrument = false;
+	let mut budget = None;
+	let mut watchdog = None;
+	let mut blocking_calls = None;
+
+	for arg in args.0 {
+		match arg {
+			AsyncArg(term, None) => {
+				if term == "boxed" || term == "boxed_send" {
+					if boxed {
+						panic!("duplicate 'boxed' argument to #[async]");
+					}
+					boxed = true;
+					send = term == "boxed_send";
+				} else if term == "catch_unwind" {
+					if catch_unwind {
+						panic!("duplicate 'catch_unwind' argument to #[async]");
+					}
+					catch_unwind = true;
+				} else if term == "cancellable" {
+					if cancellable {
+						panic!("duplicate 'cancellable' argument to #[async]");
+					}
+					cancellable = true;
+				} else if term == "instrument" {
+					if instrument {
+						panic!("duplicate 'instrument' argument to #[async]");
+					}
+					instrument = true;
+				} else {
+					panic!("unexpected #[async] argument '{}'", term);
+				}
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Int(ref size)))) if term == "inline" => {
+				if inline.is_some() {
+					panic!("duplicate 'inline' argument to #[async]");
+				}
+				inline = Some(size.value());
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Int(ref n)))) if term == "budget" => {
+				if budget.is_some() {
+					panic!("duplicate 'budget' argument to #[async]");
+				}
+				budget = Some(parse_budget(n));
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref threshold))))
+				if term == "watchdog" =>
+			{
+				if watchdog.is_some() {
+					panic!("duplicate 'watchdog' argument to #[async]");
+				}
+				watchdog = Some(parse_duration_millis(&threshold.value()));
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref list))))
+				if term == "blocking_calls" =>
+			{
+				if blocking_calls.is_some() {
+					panic!("duplicate 'blocking_calls' argument to #[async]");
+				}
+				blocking_calls = Some(parse_blocking_calls(list));
+			}
+			AsyncArg(term, Some(value)) => {
+				panic!(
+					"unexpected #[async] argument '{}'",
+					quote_cs!(#term = #value)
+				);
+			}
+		}
+	}
+
+	if boxed && inline.is_some() {
+		panic!("#[async] cannot be both `boxed` and `inline`");
+	}
+
+	if let Ok(Item::Impl(item)) = syn::parse(function.clone()) {
+		return async_impl(attribute, item);
+	}
+
+	let mut wrappers = Vec::new();
+	if let Some(millis) = watchdog {
+		wrappers.push(Wrapper::Watchdog(millis));
+	}
+	if boxed {
+		wrappers.push(Wrapper::Call(quote_cs! { ::futures::__rt::std::boxed::Box::new }));
+	} else if inline.is_some() {
+		wrappers.push(Wrapper::Call(quote_cs! { ::futures::SmallFuture::new }));
+	}
+
+	let gen_function = if catch_unwind {
+		quote_cs! { ::futures::__rt::gen_catch_unwind }
+	} else {
+		quote_cs! { ::futures::__rt::gen }
 	};
 
 	async_inner(
-		boxed,
+		wrappers,
 		function,
-		quote_cs! { ::futures::__rt::gen },
+		gen_function,
+		false,
+		AsyncOptions {
+			cancellable,
+			instrument,
+			budget,
+			blocking_calls: blocking_calls.unwrap_or_default(),
+		},
 		|output| {
 			// TODO: can we lift the restriction that `futures` must be at the root of
 			//       the crate?
 			let output_span = first_last(&output);
+			let (item, error) = result_types(output);
 			let return_ty = if boxed && !send {
 				quote_cs! {
 						::futures::__rt::std::boxed::Box<::futures::Future<
-								Item = <! as ::futures::__rt::IsResult>::Ok,
-								Error = <! as ::futures::__rt::IsResult>::Err,
+								Item = !,
+								Error = !,
 						>>
 				}
 			} else if boxed && send {
 				quote_cs! {
 						::futures::__rt::std::boxed::Box<::futures::Future<
-								Item = <! as ::futures::__rt::IsResult>::Ok,
-								Error = <! as ::futures::__rt::IsResult>::Err,
+								Item = !,
+								Error = !,
 						> + Send>
 				}
+			} else if let Some(size) = inline {
+				// Round the requested number of bytes up to a whole number of
+				// `u64` words for the storage buffer.
+				let words = Literal::u64_unsuffixed((size + 7) / 8);
+				quote_cs! {
+						::futures::SmallFuture<!, !, [u64; #words]>
+				}
 			} else {
-				// Dunno why this is buggy, hits weird typecheck errors in tests
-				//
-				// quote_cs! {
-				//     impl ::futures::Future<
-				//         Item = <#output as ::futures::__rt::MyTry>::MyOk,
-				//         Error = <#output as ::futures::__rt::MyTry>::MyError,
-				//     >
-				// }
-				quote_cs! { impl ::futures::__rt::MyFuture<!> + 'static }
+				quote_cs! {
+						impl ::futures::Future<Item = !, Error = !> + ::futures::FusedFuture + 'static
+				}
 			};
 			let return_ty = respan(return_ty.into(), &output_span);
-			replace_bang(return_ty, &output)
+			let return_ty = replace_bangs(return_ty, &[&item, &error]);
+			if cancellable {
+				quote_cs! { (#return_ty, ::futures::CancelHandle) }.into()
+			} else {
+				return_ty
+			}
 		},
 	)
 }
 
+/// Marks a method of an `#[async]` impl block which shouldn't be made async.
+const NOT_ASYNC: &str = "not_async";
+
+/// Applies `#[async]`, with the same arguments, to each method of an impl
+/// block which returns a `Result`.
+///
+/// Methods marked `#[not_async]` are left alone, as are those which already
+/// have an `#[async]` or `#[async_stream]` attribute of their own so they can
+/// use different arguments.
+fn async_impl(attribute: TokenStream, mut item: ItemImpl) -> TokenStream {
+	let items = mem::replace(&mut item.items, Vec::new());
+	for impl_item in items {
+		let mut method = match impl_item {
+			ImplItem::Method(method) => method,
+			impl_item => {
+				item.items.push(impl_item);
+				continue;
+			}
+		};
+		let len = method.attrs.len();
+		method.attrs.retain(|attr| !is_attr(attr, NOT_ASYNC));
+		let skip = method.attrs.len() != len ||
+			method.attrs.iter().any(|attr| is_attr(attr, "async") || is_attr(attr, "async_stream")) ||
+			!returns_result(&method.sig.decl.output);
+		if skip {
+			item.items.push(ImplItem::Method(method));
+			continue;
+		}
+
+		let ImplItemMethod { attrs, vis, defaultness, sig, block } = method;
+		let function = ItemFn {
+			attrs,
+			vis,
+			constness: sig.constness,
+			unsafety: sig.unsafety,
+			abi: sig.abi,
+			ident: sig.ident,
+			decl: Box::new(sig.decl),
+			block: Box::new(block),
+		};
+		// Parse the expanded fn back as a method so `default` goes after its
+		// attributes and visibility rather than in front of them
+		let function = async(attribute.clone(), function.into_token_stream().into());
+		let mut method = match syn::parse(function).expect("failed to parse expanded method") {
+			ImplItem::Method(method) => method,
+			_ => panic!("#[async] expanded a method into something else"),
+		};
+		method.defaultness = defaultness;
+		item.items.push(ImplItem::Method(method));
+	}
+	item.into_token_stream().into()
+}
+
+fn is_attr(attr: &Attribute, name: &str) -> bool {
+	attr.path.segments.len() == 1 && attr.path.segments[0].ident == name
+}
+
+/// Checks whether a method's return type is a `Result`, going by a path
+/// ending in `Result` such as `std::result::Result` or `io::Result`.
+///
+/// Typedefs named anything else can't be told apart from other types, so
+/// methods returning one which looks like a result are left alone with a
+/// warning. They can opt in with their own `#[async]`.
+fn returns_result(output: &ReturnType) -> bool {
+	let ty = match *output {
+		ReturnType::Type(_, ref ty) => ty,
+		ReturnType::Default => return false,
+	};
+	let ident = match **ty {
+		Type::Path(TypePath { qself: None, ref path }) => &path.segments.last().unwrap().value().ident,
+		_ => return false,
+	};
+	if ident == "Result" {
+		return true;
+	}
+	if ident.to_string().ends_with("Result") {
+		ident.span()
+			.unstable()
+			.warning(format!("`{}` isn't known to be a `Result`, so this method is left synchronous", ident))
+			.help("add `#[async]` to the method to make it async, or `#[not_async]` to keep it as it is")
+			.emit();
+	}
+	false
+}
+
+#[proc_macro_attribute]
+pub fn maybe_async(attribute: TokenStream, function: TokenStream) -> TokenStream {
+	// Handle arguments to the #[maybe_async] attribute, if any
+	let args = syn::parse::<AsyncArgs>(attribute).expect("failed to parse attribute arguments");
+
+	let mut blocking_name = None;
+
+	for arg in args.0 {
+		match arg {
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref name))))
+				if term == "blocking" =>
+			{
+				if blocking_name.is_some() {
+					panic!("duplicate 'blocking' argument to #[maybe_async]");
+				}
+				blocking_name = Some(name.value());
+			}
+			AsyncArg(term, None) => {
+				panic!("unexpected #[maybe_async] argument '{}'", term);
+			}
+			AsyncArg(term, Some(value)) => {
+				panic!(
+					"unexpected #[maybe_async] argument '{}'",
+					quote_cs!(#term = #value)
+				);
+			}
+		}
+	}
+
+	let async_fn: Tokens = async(Tokens::empty().into(), function.clone()).into();
+
+	let ItemFn {
+		ident,
+		vis,
+		unsafety,
+		block: _,
+		decl,
+		attrs,
+		..
+	} = match syn::parse(function).expect("failed to parse tokens as a function") {
+		Item::Fn(item) => item,
+		_ => panic!("#[maybe_async] can only be applied to functions"),
+	};
+	let FnDecl {
+		inputs,
+		output,
+		generics,
+		..
+	} = { *decl };
+	let where_clause = &generics.where_clause;
+
+	let blocking_ident = match blocking_name {
+		Some(name) => Ident::new(&name, ident.span()),
+		None => Ident::new(&format!("{}_blocking", ident), ident.span()),
+	};
+
+	// The blocking function takes the same arguments, although any patterns
+	// are replaced with plain names so they can be passed on to the async one.
+	let mut receiver = false;
+	let mut blocking_inputs = Vec::new();
+	let mut args = Vec::new();
+	for (i, input) in inputs.into_iter().enumerate() {
+		match input {
+			FnArg::Captured(ArgCaptured { pat, ty, colon_token }) => {
+				let name = match pat {
+					Pat::Ident(PatIdent { ident, .. }) => ident,
+					_ => Ident::new(&format!("__arg_{}", i), Span::call_site()),
+				};
+				if name == "self" {
+					receiver = true;
+				} else {
+					args.push(name.clone());
+				}
+				blocking_inputs.push(quote_cs! { #name #colon_token #ty });
+			}
+			FnArg::SelfRef(_) | FnArg::SelfValue(_) => {
+				receiver = true;
+				blocking_inputs.push(input.into_token_stream());
+			}
+			_ => panic!("unsupported argument to a #[maybe_async] function"),
+		}
+	}
+	let call = if receiver {
+		quote_cs! { self.#ident(#(#args),*) }
+	} else {
+		quote_cs! { #ident(#(#args),*) }
+	};
+
+	let doc = format!(" This is the blocking version of `{}`, which runs it to completion on the \
+	                   current thread.", ident);
+	let doc = LitStr::new(&doc, Span::call_site());
+	let doc_separator = if attrs.iter().any(|attr| attr.path.segments.len() == 1 &&
+		attr.path.segments[0].ident == "doc")
+	{
+		Some(quote_cs! { #[doc = ""] })
+	} else {
+		None
+	};
+
+	let output = quote_cs! {
+			#async_fn
+
+			#(#attrs)*
+			#doc_separator
+			#[doc = #doc]
+			#vis #unsafety fn #blocking_ident #generics(#(#blocking_inputs),*) #output
+					#where_clause
+			{
+					::futures::executor::block_on(#call)
+			}
+	};
+	output.into()
+}
+
 #[proc_macro_attribute]
 pub fn async_stream(attribute: TokenStream, function: TokenStream) -> TokenStream {
 	// Handle arguments to the #[async_stream] attribute, if any
-	let args =
-		syn::parse::<AsyncStreamArgs>(attribute).expect("failed to parse attribute arguments");
+	let args = syn::parse::<AsyncArgs>(attribute).expect("failed to parse attribute arguments");
 
 	let mut boxed = false;
 	let mut item_ty = None;
+	let mut catch_unwind = false;
+	let mut instrument = false;
+	let mut budget = None;
+	let mut blocking_calls = None;
 
 	for arg in args.0 {
 		match arg {
-			AsyncStreamArg(term, None) => {
+			AsyncArg(term, None) => {
 				if term == "boxed" {
 					if boxed {
 						panic!("duplicate 'boxed' argument to #[async_stream]");
 					}
 					boxed = true;
+				} else if term == "catch_unwind" {
+					if catch_unwind {
+						panic!("duplicate 'catch_unwind' argument to #[async_stream]");
+					}
+					catch_unwind = true;
+				} else if term == "instrument" {
+					if instrument {
+						panic!("duplicate 'instrument' argument to #[async_stream]");
+					}
+					instrument = true;
 				} else {
 					panic!("unexpected #[async_stream] argument '{}'", term);
 				}
 			}
-			AsyncStreamArg(term, Some(ty)) => {
+			AsyncArg(term, Some(AsyncArgValue::Type(ty))) => {
 				if term == "item" {
 					if item_ty.is_some() {
 						panic!("duplicate 'item' argument to #[async_stream]");
@@ -309,34 +696,258 @@ pub fn async_stream(attribute: TokenStream, function: TokenStream) -> TokenStrea
 					);
 				}
 			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Int(ref n)))) if term == "budget" => {
+				if budget.is_some() {
+					panic!("duplicate 'budget' argument to #[async_stream]");
+				}
+				budget = Some(parse_budget(n));
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref list))))
+				if term == "blocking_calls" =>
+			{
+				if blocking_calls.is_some() {
+					panic!("duplicate 'blocking_calls' argument to #[async_stream]");
+				}
+				blocking_calls = Some(parse_blocking_calls(list));
+			}
+			AsyncArg(term, Some(value)) => {
+				panic!(
+					"unexpected #[async_stream] argument '{}'",
+					quote_cs!(#term = #value)
+				);
+			}
 		}
 	}
 
 	let boxed = boxed;
 	let item_ty = item_ty.expect("#[async_stream] requires item type to be specified");
 
+	let mut wrappers = Vec::new();
+	if boxed {
+		wrappers.push(Wrapper::Call(quote_cs! { ::futures::__rt::std::boxed::Box::new }));
+	}
+
+	let gen_function = if catch_unwind {
+		quote_cs! { ::futures::__rt::gen_stream_catch_unwind }
+	} else {
+		quote_cs! { ::futures::__rt::gen_stream }
+	};
+
 	async_inner(
-		boxed,
+		wrappers,
 		function,
-		quote_cs! { ::futures::__rt::gen_stream },
+		gen_function,
+		true,
+		AsyncOptions {
+			instrument,
+			budget,
+			blocking_calls: blocking_calls.unwrap_or_default(),
+			..AsyncOptions::default()
+		},
 		|output| {
 			let output_span = first_last(&output);
+			let (_, error) = result_types(output);
 			let return_ty = if boxed {
 				quote_cs! {
 						::futures::__rt::std::boxed::Box<::futures::Stream<
 								Item = !,
-								Error = <! as ::futures::__rt::IsResult>::Err,
+								Error = !,
 						>>
 				}
 			} else {
-				quote_cs! { impl ::futures::__rt::MyStream<!, !> + 'static }
+				quote_cs! {
+						impl ::futures::Stream<Item = !, Error = !> + ::futures::FusedStream + 'static
+				}
 			};
 			let return_ty = respan(return_ty.into(), &output_span);
-			replace_bangs(return_ty, &[&item_ty, &output])
+			replace_bangs(return_ty, &[&item_ty, &error])
 		},
 	)
 }
 
+#[proc_macro_attribute]
+pub fn async_test(attribute: TokenStream, function: TokenStream) -> TokenStream {
+	// Handle arguments to the #[async_test] attribute, if any
+	let args = syn::parse::<AsyncArgs>(attribute).expect("failed to parse attribute arguments");
+
+	let mut timeout = None;
+	let mut should_panic = None;
+
+	for arg in args.0 {
+		match arg {
+			AsyncArg(term, None) => {
+				if term == "should_panic" {
+					if should_panic.is_some() {
+						panic!("duplicate 'should_panic' argument to #[async_test]");
+					}
+					should_panic = Some(quote_cs! { #[should_panic] });
+				} else {
+					panic!("unexpected #[async_test] argument '{}'", term);
+				}
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref expected))))
+				if term == "should_panic" =>
+			{
+				if should_panic.is_some() {
+					panic!("duplicate 'should_panic' argument to #[async_test]");
+				}
+				should_panic = Some(quote_cs! { #[should_panic(expected = #expected)] });
+			}
+			AsyncArg(term, Some(AsyncArgValue::Lit(Lit::Str(ref duration))))
+				if term == "timeout" =>
+			{
+				if timeout.is_some() {
+					panic!("duplicate 'timeout' argument to #[async_test]");
+				}
+				timeout = Some(parse_duration_millis(&duration.value()));
+			}
+			AsyncArg(term, Some(value)) => {
+				panic!(
+					"unexpected #[async_test] argument '{}'",
+					quote_cs!(#term = #value)
+				);
+			}
+		}
+	}
+
+	let ItemFn {
+		ident,
+		block,
+		decl,
+		attrs,
+		..
+	} = match syn::parse(function).expect("failed to parse tokens as a function") {
+		Item::Fn(item) => item,
+		_ => panic!("#[async_test] can only be applied to functions"),
+	};
+	if !decl.inputs.is_empty() {
+		panic!("#[async_test] functions cannot take arguments");
+	}
+	if !decl.generics.params.is_empty() {
+		panic!("#[async_test] functions cannot be generic");
+	}
+	// The body becomes an ordinary `#[async]` function nested inside of the
+	// test, which then drives the future it returns to completion.
+	let inner = nested_async_fn(&ident, &decl.inputs, decl.output, block);
+
+	let timeout = match timeout {
+		Some(millis) => {
+			let millis = Literal::u64_unsuffixed(millis);
+			quote_cs! { ::futures::__rt::std::option::Option::Some(#millis) }
+		}
+		None => quote_cs! { ::futures::__rt::std::option::Option::None },
+	};
+
+	let output = quote_cs! {
+			#[test]
+			#should_panic
+			#(#attrs)*
+			fn #ident() {
+					#inner
+					::futures::__rt::run_test(#ident(), #timeout)
+			}
+	};
+	output.into()
+}
+
+#[proc_macro_attribute]
+pub fn async_main(attribute: TokenStream, function: TokenStream) -> TokenStream {
+	// Handle arguments to the #[async_main] attribute, if any
+	let args = syn::parse::<AsyncArgs>(attribute).expect("failed to parse attribute arguments");
+
+	let mut tokio = false;
+
+	for arg in args.0 {
+		match arg {
+			AsyncArg(term, None) => {
+				if term == "tokio" {
+					if tokio {
+						panic!("duplicate 'tokio' argument to #[async_main]");
+					}
+					tokio = true;
+				} else {
+					panic!("unexpected #[async_main] argument '{}'", term);
+				}
+			}
+			AsyncArg(term, Some(value)) => {
+				panic!(
+					"unexpected #[async_main] argument '{}'",
+					quote_cs!(#term = #value)
+				);
+			}
+		}
+	}
+
+	let ItemFn {
+		ident,
+		vis,
+		block,
+		decl,
+		attrs,
+		..
+	} = match syn::parse(function).expect("failed to parse tokens as a function") {
+		Item::Fn(item) => item,
+		_ => panic!("#[async_main] can only be applied to functions"),
+	};
+	if !decl.generics.params.is_empty() {
+		panic!("#[async_main] functions cannot be generic");
+	}
+	let run = if tokio {
+		// The event loop's handle is passed in as the only argument, if the
+		// function asks for it.
+		match decl.inputs.len() {
+			0 => quote_cs! { ::futures::__rt::run_main_tokio(|_| #ident()) },
+			1 => quote_cs! { ::futures::__rt::run_main_tokio(#ident) },
+			_ => panic!("#[async_main(tokio)] functions take at most one argument, \
+			             the event loop's `Handle`"),
+		}
+	} else {
+		if !decl.inputs.is_empty() {
+			panic!("#[async_main] functions cannot take arguments");
+		}
+		quote_cs! { ::futures::__rt::run_main(#ident()) }
+	};
+	let inner = nested_async_fn(&ident, &decl.inputs, decl.output, block);
+
+	let output = quote_cs! {
+			#(#attrs)*
+			#vis fn #ident() {
+					#inner
+					#run
+			}
+	};
+	output.into()
+}
+
+/// Expands the body of a function with an attribute like `#[async_test]` into
+/// an `#[async]` function of the same name, to be nested inside of it.
+fn nested_async_fn(
+	ident: &Ident,
+	inputs: &Punctuated<FnArg, Token![,]>,
+	output: ReturnType,
+	block: Box<Block>,
+) -> Tokens {
+	let inner = quote_cs! {
+			fn #ident(#inputs) #output #block
+	};
+	async_inner(
+		Vec::new(),
+		inner.into(),
+		quote_cs! { ::futures::__rt::gen },
+		false,
+		AsyncOptions::default(),
+		|output| {
+			let output_span = first_last(&output);
+			let (item, error) = result_types(output);
+			let return_ty = quote_cs! {
+					impl ::futures::Future<Item = !, Error = !> + ::futures::FusedFuture + 'static
+			};
+			let return_ty = respan(return_ty.into(), &output_span);
+			replace_bangs(return_ty, &[&item, &error])
+		},
+	).into()
+}
+
 #[proc_macro]
 pub fn async_block(input: TokenStream) -> TokenStream {
 	let mut t_tree = TokenTree::Group(proc_macro::Group::new(Delimiter::Brace, input));
@@ -344,19 +955,32 @@ pub fn async_block(input: TokenStream) -> TokenStream {
 	let input = TokenStream::from(t_tree);
 
 	let expr = syn::parse(input).expect("failed to parse tokens as an expression");
-	let expr = ExpandAsyncFor.fold_expr(expr);
+	borrows::check_expr(&expr);
+	let expr = lint::blocking_calls_expr(expr);
+	let expr = ExpandAsyncFor { cancellable: false }.fold_expr(expr);
+	let expr = MarkAsyncContext::new(false, false).fold_expr(expr);
 
 	let mut tokens = quote_cs! {
 			::futures::__rt::gen
 	};
+	let name = "async_block!";
 
 	// Use some manual token construction here instead of `quote_cs!` to ensure
 	// that we get the `call_site` span instead of the default span.
 	let span = Span::call_site();
 	syn::token::Paren(span).surround(&mut tokens, |tokens| {
+		let name = LitStr::new(name, span);
+		(quote_cs! {
+				&::futures::__rt::Meta {
+						name: #name,
+						instrument: false,
+						budget: ::futures::__rt::std::option::Option::None,
+				},
+		}).to_tokens(tokens);
 		syn::token::Move(span).to_tokens(tokens);
 		syn::token::OrOr([span, span]).to_tokens(tokens);
 		syn::token::Brace(span).surround(tokens, |tokens| {
+			await_context().to_tokens(tokens);
 			(quote_cs! {
 					if false { yield ::futures::Async::NotReady }
 			}).to_tokens(tokens);
@@ -373,19 +997,32 @@ pub fn async_stream_block(input: TokenStream) -> TokenStream {
 	t_tree.set_span(proc_macro::Span::call_site());
 	let input = TokenStream::from(t_tree);
 	let expr = syn::parse(input).expect("failed to parse tokens as an expression");
-	let expr = ExpandAsyncFor.fold_expr(expr);
+	borrows::check_expr(&expr);
+	let expr = lint::blocking_calls_expr(expr);
+	let expr = ExpandAsyncFor { cancellable: false }.fold_expr(expr);
+	let expr = MarkAsyncContext::new(true, false).fold_expr(expr);
 
 	let mut tokens = quote_cs! {
 			::futures::__rt::gen_stream
 	};
+	let name = "async_stream_block!";
 
 	// Use some manual token construction here instead of `quote_cs!` to ensure
 	// that we get the `call_site` span instead of the default span.
 	let span = Span::call_site();
 	syn::token::Paren(span).surround(&mut tokens, |tokens| {
+		let name = LitStr::new(name, span);
+		(quote_cs! {
+				&::futures::__rt::Meta {
+						name: #name,
+						instrument: false,
+						budget: ::futures::__rt::std::option::Option::None,
+				},
+		}).to_tokens(tokens);
 		syn::token::Move(span).to_tokens(tokens);
 		syn::token::OrOr([span, span]).to_tokens(tokens);
 		syn::token::Brace(span).surround(tokens, |tokens| {
+			await_context().to_tokens(tokens);
 			(quote_cs! {
 					if false { yield ::futures::Async::NotReady }
 			}).to_tokens(tokens);
@@ -396,7 +1033,85 @@ pub fn async_stream_block(input: TokenStream) -> TokenStream {
 	tokens.into()
 }
 
-struct ExpandAsyncFor;
+#[proc_macro]
+pub fn async_closure(input: TokenStream) -> TokenStream {
+	async_closure_inner(input, false)
+}
+
+#[proc_macro]
+pub fn async_stream_closure(input: TokenStream) -> TokenStream {
+	async_closure_inner(input, true)
+}
+
+/// Turns a closure into one returning a new future, or stream, each time it's
+/// called, with the closure's arguments moved into it.
+fn async_closure_inner(input: TokenStream, stream: bool) -> TokenStream {
+	let (name, gen_function) = if stream {
+		("async_stream_closure!", quote_cs! { ::futures::__rt::gen_stream })
+	} else {
+		("async_closure!", quote_cs! { ::futures::__rt::gen })
+	};
+	let ExprClosure {
+		attrs,
+		capture,
+		inputs,
+		output,
+		body,
+		..
+	} = match syn::parse(input).expect("failed to parse tokens as an expression") {
+		Expr::Closure(closure) => closure,
+		_ => panic!("{} expects a closure", name),
+	};
+
+	borrows::check_expr(&body);
+	let body = lint::blocking_calls_expr(*body);
+	let body = ExpandAsyncFor { cancellable: false }.fold_expr(body);
+	let body = MarkAsyncContext::new(stream, false).fold_expr(body);
+
+	// The closure's return type, if it has one, is that of the body and so
+	// belongs on the generator instead.
+	let output = match output {
+		ReturnType::Type(rarrow_token, ty) => Some(quote_cs! { #rarrow_token #ty }),
+		ReturnType::Default => None,
+	};
+	let name = LitStr::new(name, Span::call_site());
+	let await_context = await_context();
+	let tokens = quote_cs! {
+			#(#attrs)*
+			#capture |#inputs| {
+					#gen_function(
+							&::futures::__rt::Meta {
+									name: #name,
+									instrument: false,
+									budget: ::futures::__rt::std::option::Option::None,
+							},
+							move || #output {
+									#await_context
+									if false { yield ::futures::Async::NotReady }
+									#body
+							},
+					)
+			}
+	};
+	tokens.into()
+}
+
+/// Defines the `__await_context!` macro which `await!` and friends expect to
+/// find inside of a generator. It shadows the exported one that reports their
+/// use anywhere else, which also catches them inside of helper macros.
+fn await_context() -> Tokens {
+	quote_cs! {
+			#[allow(unused_macros)]
+			macro_rules! __await_context {
+					($name:ident, $($t:tt)*) => ($($t)*)
+			}
+	}
+}
+
+struct ExpandAsyncFor {
+	/// Whether the loop checks for cancellation, see `#[async(cancellable)]`.
+	cancellable: bool,
+}
 
 impl Fold for ExpandAsyncFor {
 	fn fold_expr(&mut self, expr: Expr) -> Expr {
@@ -426,9 +1141,32 @@ impl Fold for ExpandAsyncFor {
 			expr,
 			body,
 			label,
+			for_token,
 			..
 		} = all;
 
+		// Like `await!`, record where the function is waiting before each poll
+		// for traces and diagnostics, pointing at the loop's `for`.
+		let site = (for_token.0, for_token.0);
+		let file = respan(quote_cs! { file!() }.into(), &site);
+		let line = respan(quote_cs! { line!() }.into(), &site);
+
+		// In cancellable functions the loop registers to be woken up by the
+		// cancellation flag and then checks it before each poll, returning
+		// `Cancelled` converted into the function's error.
+		let check_cancel = if self.cancellable {
+			quote_cs! {
+				__cancel.register();
+				if __cancel.take() {
+					return futures_await::__rt::std::result::Result::Err(
+						futures_await::__rt::std::convert::From::from(futures_await::Cancelled)
+					)
+				}
+			}
+		} else {
+			Tokens::empty()
+		};
+
 		// Basically just expand to a `poll` loop
 		let tokens = quote_cs! {{
 				let mut __stream = #expr;
@@ -436,9 +1174,19 @@ impl Fold for ExpandAsyncFor {
 				loop {
 						let #pat = {
 								extern crate futures_await;
+								futures_await::__rt::record_await(#file, #line);
+								if futures_await::__rt::inject_not_ready() {
+										futures_await::task::current().notify();
+										yield futures_await::Async::NotReady;
+								}
+								#check_cancel
 								let r = futures_await::Stream::poll(&mut __stream)?;
 								match r {
 										futures_await::Async::Ready(e) => {
+												if futures_await::__rt::consume_budget() {
+														futures_await::task::current().notify();
+														yield futures_await::Async::NotReady;
+												}
 												match e {
 														futures_await::__rt::std::option::Option::Some(e) => e,
 														futures_await::__rt::std::option::Option::None => break,
@@ -463,6 +1211,236 @@ impl Fold for ExpandAsyncFor {
 	}
 }
 
+/// Macros which may only be used directly inside of an async function or block.
+const ASYNC_MACROS: &[&str] = &[
+	"async_scope",
+	"await",
+	"await_blocking",
+	"await_item",
+	"await_poll",
+	"on_cancel",
+	"yield_now",
+];
+
+/// Macros which wait on something and so check for cancellation in
+/// `#[async(cancellable)]` functions.
+const AWAIT_MACROS: &[&str] = &[
+	"async_scope",
+	"await",
+	"await_blocking",
+	"await_item",
+	"await_poll",
+];
+
+/// Macros which may only be used directly inside of an async stream function
+/// or block.
+const ASYNC_STREAM_MACROS: &[&str] = &["stream_yield"];
+
+/// Macros which define their own async context.
+const ASYNC_BLOCK_MACROS: &[&str] = &[
+	"async_block",
+	"async_closure",
+	"async_stream_block",
+	"async_stream_closure",
+];
+
+/// Marks invocations of macros like `await!` as being inside an async context.
+///
+/// Macros like `await!` expand to a `yield`, which only works when they're
+/// used directly in the body of the generator we create. Invoked elsewhere the
+/// compiler's errors are quite confusing, so where we can tell a macro is in
+/// the wrong place, like `await!` in a closure nested inside an async function
+/// or `stream_yield!` in an async function which isn't a stream, its
+/// invocation is replaced with a targeted error. Everything else gets a
+/// `@__context` marker at the start of its arguments, through which
+/// cancellable functions hand over their cancellation flag. Invocations
+/// without the marker, for example ones coming from another macro, still
+/// expand as usual.
+struct MarkAsyncContext {
+	stream: bool,
+	cancellable: bool,
+	/// Whether we're inside of a closure nested in the async body, which the
+	/// generator can't be suspended from.
+	in_closure: bool,
+}
+
+impl MarkAsyncContext {
+	fn new(stream: bool, cancellable: bool) -> MarkAsyncContext {
+		MarkAsyncContext {
+			stream,
+			cancellable,
+			in_closure: false,
+		}
+	}
+
+	fn is_context_macro(&self, name: &str) -> bool {
+		ASYNC_MACROS.contains(&name) || (self.stream && ASYNC_STREAM_MACROS.contains(&name))
+	}
+
+	/// Returns the error for `name!` if it's being used in the wrong context.
+	fn misuse(&self, name: &str) -> Option<String> {
+		let is_async = ASYNC_MACROS.contains(&name);
+		let is_stream = ASYNC_STREAM_MACROS.contains(&name);
+		if self.in_closure && is_async {
+			Some(format!(
+				"{}! can only be used inside #[async] or async_block!, and not inside \
+				 closures or functions nested within them",
+				name
+			))
+		} else if is_stream && (self.in_closure || !self.stream) {
+			Some(format!(
+				"{}! can only be used inside #[async_stream] or async_stream_block!, \
+				 and not inside closures or functions nested within them",
+				name
+			))
+		} else {
+			None
+		}
+	}
+
+	fn mark_macro(&self, mut mac: Macro) -> Macro {
+		let name = mac.path.segments.last().unwrap().value().ident.to_string();
+		if ASYNC_BLOCK_MACROS.contains(&&*name) {
+			return mac;
+		}
+		if let Some(msg) = self.misuse(&name) {
+			let span = mac.path.segments.last().unwrap().value().ident.span();
+			mac.path = Ident::new("compile_error", span).into();
+			mac.tts = Literal::string(&msg).into_token_stream();
+			return mac;
+		}
+		let mut tts = self.mark_tokens(mac.tts);
+		if self.is_context_macro(&name) {
+			tts = self.marked(&name, tts);
+		}
+		mac.tts = tts;
+		mac
+	}
+
+	/// Marks the macro invocations inside of the arguments to another macro.
+	///
+	/// We can't parse these as expressions in general, so this works on the
+	/// raw tokens looking for `name!(...)`.
+	fn mark_tokens(&self, tokens: Tokens) -> Tokens {
+		let mut tokens = tokens.into_iter().peekable();
+		let mut new_tokens = Vec::new();
+		while let Some(token) = tokens.next() {
+			match token {
+				proc_macro2::TokenTree::Group(group) => {
+					let mut new_group =
+						proc_macro2::Group::new(group.delimiter(), self.mark_tokens(group.stream()));
+					new_group.set_span(group.span());
+					new_tokens.push(new_group.into());
+				}
+				proc_macro2::TokenTree::Ident(ident) => {
+					let name = ident.to_string();
+					let is_macro = match tokens.peek() {
+						Some(&proc_macro2::TokenTree::Punct(ref p)) => p.as_char() == '!',
+						_ => false,
+					};
+					if !is_macro {
+						new_tokens.push(ident.into());
+						continue;
+					}
+					let bang = tokens.next().unwrap();
+					let group = match tokens.peek() {
+						Some(&proc_macro2::TokenTree::Group(ref group)) => group.clone(),
+						_ => {
+							new_tokens.push(ident.into());
+							new_tokens.push(bang);
+							continue;
+						}
+					};
+					tokens.next();
+					if ASYNC_BLOCK_MACROS.contains(&&*name) {
+						new_tokens.push(ident.into());
+						new_tokens.push(bang);
+						new_tokens.push(group.into());
+						continue;
+					}
+					let stream = match self.misuse(&name) {
+						Some(msg) => {
+							new_tokens.push(Ident::new("compile_error", ident.span()).into());
+							Literal::string(&msg).into_token_stream()
+						}
+						None => {
+							new_tokens.push(ident.into());
+							let stream = self.mark_tokens(group.stream());
+							if self.is_context_macro(&name) {
+								self.marked(&name, stream)
+							} else {
+								stream
+							}
+						}
+					};
+					new_tokens.push(bang);
+					let mut new_group = proc_macro2::Group::new(group.delimiter(), stream);
+					new_group.set_span(group.span());
+					new_tokens.push(new_group.into());
+				}
+				token => new_tokens.push(token),
+			}
+		}
+		new_tokens.into_iter().collect()
+	}
+
+	/// Prepends the marker telling `name!` that it's in an async context.
+	///
+	/// In cancellable functions the awaiting macros are also handed the
+	/// cancellation flag to check while they're waiting.
+	fn marked(&self, name: &str, tokens: Tokens) -> Tokens {
+		let mut marked = if self.cancellable && AWAIT_MACROS.contains(&name) {
+			quote_cs! { @__context @__cancel __cancel, }
+		} else {
+			quote_cs! { @__context }
+		};
+		marked.extend(tokens);
+		marked
+	}
+}
+
+impl Fold for MarkAsyncContext {
+	fn fold_macro(&mut self, mac: Macro) -> Macro {
+		self.mark_macro(mac)
+	}
+
+	fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+		match stmt {
+			Stmt::Item(Item::Macro(mut item)) => {
+				item.mac = self.mark_macro(item.mac);
+				Stmt::Item(Item::Macro(item))
+			}
+			stmt => fold::fold_stmt(self, stmt),
+		}
+	}
+
+	// Closures aren't part of the generator, so nothing in them can suspend it
+	fn fold_expr_closure(&mut self, expr: ExprClosure) -> ExprClosure {
+		let in_closure = mem::replace(&mut self.in_closure, true);
+		let expr = fold::fold_expr_closure(self, expr);
+		self.in_closure = in_closure;
+		expr
+	}
+
+	// Don't recurse into items
+	fn fold_item(&mut self, item: Item) -> Item {
+		item
+	}
+}
+
+/// Splits the declared return type of an async function into its `Item` and
+/// `Error` types.
+///
+/// These are projected through `IntoFuture`, which `Result` implements, so
+/// that typedefs like `io::Result<T>` work without guessing at what a type
+/// name refers to, and without mentioning `__rt` in the generated signature.
+fn result_types(output: &Type) -> (Tokens, Tokens) {
+	(
+		quote_cs! { <#output as ::futures::IntoFuture>::Item },
+		quote_cs! { <#output as ::futures::IntoFuture>::Error },
+	)
+}
+
 fn first_last(tokens: &ToTokens) -> (Span, Span) {
 	let mut spans = Tokens::empty();
 	tokens.to_tokens(&mut spans);
@@ -491,17 +1469,6 @@ fn respan(
 	new_tokens.into_iter().collect()
 }
 
-fn replace_bang(input: proc_macro2::TokenStream, tokens: &ToTokens) -> proc_macro2::TokenStream {
-	let mut new_tokens = Tokens::empty();
-	for token in input.into_iter() {
-		match token {
-			proc_macro2::TokenTree::Punct(ref op) if op.as_char() == '!' => tokens.to_tokens(&mut new_tokens),
-			_ => token.to_tokens(&mut new_tokens),
-		}
-	}
-	new_tokens.into()
-}
-
 fn replace_bangs(
 	input: proc_macro2::TokenStream,
 	replacements: &[&ToTokens],
@@ -519,23 +1486,104 @@ fn replace_bangs(
 	new_tokens.into()
 }
 
-struct AsyncStreamArg(syn::Ident, Option<syn::Type>);
+/// Options affecting how the body of an async function is generated.
+#[derive(Default)]
+struct AsyncOptions {
+	/// Whether `await!` checks for cancellation, see `#[async(cancellable)]`.
+	cancellable: bool,
+	/// Whether polls are reported to the global instrument.
+	instrument: bool,
+	/// Number of times the body may make progress in one poll before it's
+	/// forced to yield.
+	budget: Option<u64>,
+	/// Additional functions and methods flagged by the blocking call lint.
+	blocking_calls: Vec<String>,
+}
+
+/// Layers wrapped around the generated future, innermost first.
+enum Wrapper {
+	/// Calls the given function with the future.
+	Call(Tokens),
+	/// Monitors the future with a `Watchdog`, given the threshold in
+	/// milliseconds.
+	Watchdog(u64),
+}
+
+/// Parses a comma separated list of paths and `.method`s like
+/// `"my_crate::sync_fetch, .block_on"`.
+fn parse_blocking_calls(list: &LitStr) -> Vec<String> {
+	list.value()
+		.split(',')
+		.map(|s| s.trim())
+		.filter(|s| !s.is_empty())
+		.map(|s| s.to_string())
+		.collect()
+}
+
+fn parse_budget(n: &LitInt) -> u64 {
+	match n.value() {
+		0 => panic!("'budget' must be at least 1"),
+		n if n > u32::max_value() as u64 => panic!("'budget' is too large"),
+		n => n,
+	}
+}
+
+/// Parses durations like `500ms`, `5s` or `2m` into milliseconds.
+fn parse_duration_millis(s: &str) -> u64 {
+	let split = s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+	let (amount, unit) = s.split_at(split);
+	let scale = match unit.trim() {
+		"ms" => 1,
+		"s" => 1_000,
+		"m" => 60_000,
+		"h" => 3_600_000,
+		_ => panic!("invalid duration \"{}\", expected something like \"5s\" or \"500ms\"", s),
+	};
+	match amount.parse::<u64>() {
+		Ok(amount) => amount * scale,
+		Err(_) => panic!("invalid duration \"{}\", expected something like \"5s\" or \"500ms\"", s),
+	}
+}
+
+struct AsyncArg(syn::Ident, Option<AsyncArgValue>);
 
-impl synom::Synom for AsyncStreamArg {
+impl synom::Synom for AsyncArg {
 	named!(parse -> Self, do_parse!(
         i: syn!(syn::Ident) >>
         p: option!(do_parse!(
             syn!(syn::token::Eq) >>
-            p: syn!(syn::Type) >>
+            p: syn!(AsyncArgValue) >>
             (p))) >>
-        (AsyncStreamArg(i, p))));
+        (AsyncArg(i, p))));
+}
+
+enum AsyncArgValue {
+	Lit(syn::Lit),
+	Type(syn::Type),
+}
+
+impl synom::Synom for AsyncArgValue {
+	named!(parse -> Self, alt!(
+        syn!(syn::Lit) => { AsyncArgValue::Lit }
+        |
+        syn!(syn::Type) => { AsyncArgValue::Type }
+    ));
+}
+
+impl ToTokens for AsyncArgValue {
+	fn to_tokens(&self, tokens: &mut Tokens) {
+		match *self {
+			AsyncArgValue::Lit(ref lit) => lit.to_tokens(tokens),
+			AsyncArgValue::Type(ref ty) => ty.to_tokens(tokens),
+		}
+	}
 }
 
-struct AsyncStreamArgs(Vec<AsyncStreamArg>);
+struct AsyncArgs(Vec<AsyncArg>);
 
-impl synom::Synom for AsyncStreamArgs {
+impl synom::Synom for AsyncArgs {
 	named!(parse -> Self, map!(
-        option!(call!(Punctuated::<AsyncStreamArg, syn::token::Comma>::parse_separated_nonempty)),
-        |p| AsyncStreamArgs(p.map(|d| d.into_iter().collect()).unwrap_or_default())
+        option!(call!(Punctuated::<AsyncArg, syn::token::Comma>::parse_separated_nonempty)),
+        |p| AsyncArgs(p.map(|d| d.into_iter().collect()).unwrap_or_default())
     ));
 }
@@ -0,0 +1,193 @@
+//! Lints run over the bodies of async functions and blocks.
+//!
+//! These are purely advisory, they emit warnings through the compiler's
+//! diagnostic machinery but otherwise don't change the code that's generated.
+
+use quote::ToTokens;
+use syn::fold::{self, Fold};
+use syn::spanned::Spanned;
+use syn::*;
+
+/// Functions which are known to block the current thread.
+///
+/// A call is considered to match one of these paths if the path it's written
+/// with, which must have at least two segments, is a suffix of it. For example
+/// both `thread::sleep(..)` and `std::thread::sleep(..)` match.
+const BLOCKING_PATHS: &[&str] = &[
+	"std::fs::File::create",
+	"std::fs::File::open",
+	"std::fs::copy",
+	"std::fs::create_dir",
+	"std::fs::create_dir_all",
+	"std::fs::metadata",
+	"std::fs::read",
+	"std::fs::read_dir",
+	"std::fs::read_to_string",
+	"std::fs::remove_dir_all",
+	"std::fs::remove_file",
+	"std::fs::rename",
+	"std::fs::write",
+	"std::net::TcpStream::connect",
+	"std::thread::sleep",
+	"futures::Future::wait",
+	"futures::Stream::wait",
+];
+
+/// Methods which are known to block the current thread.
+const BLOCKING_METHODS: &[&str] = &["recv", "wait"];
+
+/// Attribute which silences the blocking call lint for a statement or
+/// expression.
+const ALLOW_BLOCKING: &str = "allow_blocking";
+
+/// Warns about calls to functions which are known to block inside of `block`.
+///
+/// Blocking calls inside of an async function stall the whole event loop it's
+/// running on rather than just the one future. Besides the built in lists,
+/// `extra` names more functions to flag, with methods written with a leading
+/// `.`, for example `my_crate::sync_fetch` or `.block_on`. Specific statements
+/// can be annotated with `#[allow_blocking]` to silence this, and that
+/// attribute is removed here.
+pub fn blocking_calls(block: Block, extra: &[String]) -> Block {
+	BlockingCalls::new(extra).fold_block(block)
+}
+
+/// Same as `blocking_calls`, but for the body of an async block.
+pub fn blocking_calls_expr(expr: Expr) -> Expr {
+	BlockingCalls::new(&[]).fold_expr(expr)
+}
+
+struct BlockingCalls {
+	paths: Vec<Vec<String>>,
+	methods: Vec<String>,
+	allowed: usize,
+}
+
+impl BlockingCalls {
+	fn new(extra: &[String]) -> BlockingCalls {
+		let mut paths = BLOCKING_PATHS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+		let mut methods = BLOCKING_METHODS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+		for entry in extra {
+			if entry.starts_with('.') {
+				methods.push(entry[1..].to_string());
+			} else {
+				paths.push(entry.to_string());
+			}
+		}
+		BlockingCalls {
+			paths: paths
+				.iter()
+				.map(|p| p.split("::").map(|s| s.to_string()).collect())
+				.collect(),
+			methods,
+			allowed: 0,
+		}
+	}
+
+	fn is_blocking_path(&self, path: &Path) -> bool {
+		let segments = path.segments
+			.iter()
+			.map(|s| s.ident.to_string())
+			.collect::<Vec<_>>();
+		segments.len() >= 2 && self.paths.iter().any(|p| p.ends_with(&segments))
+	}
+
+	fn check(&self, expr: &Expr) {
+		match *expr {
+			Expr::Call(ref call) => {
+				let path = match *call.func {
+					Expr::Path(ExprPath { ref path, .. }) => path,
+					_ => return,
+				};
+				if !self.is_blocking_path(path) {
+					return;
+				}
+				let name = path.segments.last().unwrap().value().ident.to_string();
+				let path = path.into_token_stream().to_string().replace(" ", "");
+				warn_blocking(call.span(), &format!("`{}`", path), name == "wait");
+			}
+			Expr::MethodCall(ref call) => {
+				let name = call.method.to_string();
+				if self.methods.contains(&name) {
+					warn_blocking(call.method.span(), &format!("`.{}()`", name), name == "wait");
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+fn warn_blocking(span: proc_macro2::Span, what: &str, is_wait: bool) {
+	let help = if is_wait {
+		"use `await!` to wait for this without blocking the thread"
+	} else {
+		"blocking calls stall all other futures running on the same thread, \
+		 consider moving this work onto a thread pool"
+	};
+	span.unstable()
+		.warning(format!("{} blocks the current thread inside of an async function", what))
+		.help(help)
+		.note("add `#[allow_blocking]` to the statement to silence this warning")
+		.emit();
+}
+
+/// Removes `#[allow_blocking]` from `attrs`, returning whether it was there.
+fn strip_allow_blocking(attrs: &mut Vec<Attribute>) -> bool {
+	let len = attrs.len();
+	attrs.retain(|attr| {
+		!(attr.path.segments.len() == 1 && attr.path.segments[0].ident == ALLOW_BLOCKING)
+	});
+	attrs.len() != len
+}
+
+fn expr_attrs(expr: &mut Expr) -> Option<&mut Vec<Attribute>> {
+	match *expr {
+		Expr::Call(ref mut e) => Some(&mut e.attrs),
+		Expr::MethodCall(ref mut e) => Some(&mut e.attrs),
+		Expr::Macro(ref mut e) => Some(&mut e.attrs),
+		Expr::Block(ref mut e) => Some(&mut e.attrs),
+		Expr::Unsafe(ref mut e) => Some(&mut e.attrs),
+		Expr::If(ref mut e) => Some(&mut e.attrs),
+		Expr::IfLet(ref mut e) => Some(&mut e.attrs),
+		Expr::Match(ref mut e) => Some(&mut e.attrs),
+		Expr::Loop(ref mut e) => Some(&mut e.attrs),
+		Expr::While(ref mut e) => Some(&mut e.attrs),
+		Expr::WhileLet(ref mut e) => Some(&mut e.attrs),
+		Expr::ForLoop(ref mut e) => Some(&mut e.attrs),
+		Expr::Assign(ref mut e) => Some(&mut e.attrs),
+		Expr::Try(ref mut e) => Some(&mut e.attrs),
+		_ => None,
+	}
+}
+
+impl Fold for BlockingCalls {
+	fn fold_local(&mut self, mut local: Local) -> Local {
+		let allowed = strip_allow_blocking(&mut local.attrs);
+		self.allowed += allowed as usize;
+		let local = fold::fold_local(self, local);
+		self.allowed -= allowed as usize;
+		local
+	}
+
+	fn fold_expr(&mut self, mut expr: Expr) -> Expr {
+		let allowed = expr_attrs(&mut expr).map(strip_allow_blocking).unwrap_or(false);
+		self.allowed += allowed as usize;
+		if self.allowed == 0 {
+			self.check(&expr);
+		}
+		let expr = fold::fold_expr(self, expr);
+		self.allowed -= allowed as usize;
+		expr
+	}
+
+	// Closures are frequently used to run code elsewhere, like on a thread
+	// pool, so don't recurse into them
+	fn fold_expr_closure(&mut self, expr: ExprClosure) -> ExprClosure {
+		expr
+	}
+
+	// Don't recurse into items
+	fn fold_item(&mut self, item: Item) -> Item {
+		item
+	}
+}
@@ -8,13 +8,72 @@
 // TODO: how to define this in the `futures_await` crate but have it still
 // importable via `futurses_await::prelude::await`?
 
+/// Checks that a macro like `await!` which wasn't marked by `#[async]` is
+/// still inside of an async function or block, for example because it came
+/// from a helper macro.
+///
+/// Async functions and blocks define their own `__await_context!` which
+/// shadows this one and expands to the code it's given, so this version is
+/// only reached from outside of them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __await_context {
+    (stream_yield, $($t:tt)*) => (compile_error!(
+        "`stream_yield!` can only be used inside `#[async_stream]` or `async_stream_block!`"
+    ));
+    ($name:ident, $($t:tt)*) => (compile_error!(concat!(
+        "`", stringify!($name), "!` can only be used inside `#[async]` or `async_block!`"
+    )));
+}
+
 #[macro_export]
 macro_rules! await {
-    ($e:expr) => ({
+    (@__context @__cancel $cancel:ident, $e:expr) => ({
+        let mut future = $e;
+        ::futures::__rt::record_await(file!(), line!());
+        if ::futures::__rt::inject_not_ready() {
+            ::futures::task::current().notify();
+            yield ::futures::Async::NotReady
+        }
+        loop {
+            // Register before checking so a cancellation in between still
+            // wakes us up
+            $cancel.register();
+            if $cancel.take() {
+                return ::futures::__rt::std::result::Result::Err(
+                    ::futures::__rt::std::convert::From::from(::futures::Cancelled)
+                )
+            }
+            match ::futures::Future::poll(&mut future) {
+                ::futures::__rt::std::result::Result::Ok(::futures::Async::Ready(e)) => {
+                    if ::futures::__rt::consume_budget() {
+                        ::futures::task::current().notify();
+                        yield ::futures::Async::NotReady
+                    }
+                    break ::futures::__rt::std::result::Result::Ok(e)
+                }
+                ::futures::__rt::std::result::Result::Ok(::futures::Async::NotReady) => {}
+                ::futures::__rt::std::result::Result::Err(e) => {
+                    break ::futures::__rt::std::result::Result::Err(e)
+                }
+            }
+            yield ::futures::Async::NotReady
+        }
+    });
+    (@__context $e:expr) => ({
         let mut future = $e;
+        ::futures::__rt::record_await(file!(), line!());
+        if ::futures::__rt::inject_not_ready() {
+            ::futures::task::current().notify();
+            yield ::futures::Async::NotReady
+        }
         loop {
             match ::futures::Future::poll(&mut future) {
                 ::futures::__rt::std::result::Result::Ok(::futures::Async::Ready(e)) => {
+                    if ::futures::__rt::consume_budget() {
+                        ::futures::task::current().notify();
+                        yield ::futures::Async::NotReady
+                    }
                     break ::futures::__rt::std::result::Result::Ok(e)
                 }
                 ::futures::__rt::std::result::Result::Ok(::futures::Async::NotReady) => {}
@@ -24,7 +83,8 @@ macro_rules! await {
             }
             yield ::futures::Async::NotReady
         }
-    })
+    });
+    ($e:expr) => (__await_context!(await, await!(@__context $e)))
 }
 
 ///
@@ -34,10 +94,51 @@ macro_rules! await {
 
 #[macro_export]
 macro_rules! await_item {
-    ($e:expr) => ({
+    (@__context @__cancel $cancel:ident, $e:expr) => ({
+        ::futures::__rt::record_await(file!(), line!());
+        if ::futures::__rt::inject_not_ready() {
+            ::futures::task::current().notify();
+            yield ::futures::Async::NotReady
+        }
+        loop {
+            // Register before checking so a cancellation in between still
+            // wakes us up
+            $cancel.register();
+            if $cancel.take() {
+                return ::futures::__rt::std::result::Result::Err(
+                    ::futures::__rt::std::convert::From::from(::futures::Cancelled)
+                )
+            }
+            match ::futures::Stream::poll(&mut $e) {
+                ::futures::__rt::std::result::Result::Ok(::futures::Async::Ready(e)) => {
+                    if ::futures::__rt::consume_budget() {
+                        ::futures::task::current().notify();
+                        yield ::futures::Async::NotReady
+                    }
+                    break ::futures::__rt::std::result::Result::Ok(e)
+                }
+                ::futures::__rt::std::result::Result::Ok(::futures::Async::NotReady) => {}
+                ::futures::__rt::std::result::Result::Err(e) => {
+                    break ::futures::__rt::std::result::Result::Err(e)
+                }
+            }
+
+            yield ::futures::Async::NotReady
+        }
+    });
+    (@__context $e:expr) => ({
+        ::futures::__rt::record_await(file!(), line!());
+        if ::futures::__rt::inject_not_ready() {
+            ::futures::task::current().notify();
+            yield ::futures::Async::NotReady
+        }
         loop {
             match ::futures::Stream::poll(&mut $e) {
                 ::futures::__rt::std::result::Result::Ok(::futures::Async::Ready(e)) => {
+                    if ::futures::__rt::consume_budget() {
+                        ::futures::task::current().notify();
+                        yield ::futures::Async::NotReady
+                    }
                     break ::futures::__rt::std::result::Result::Ok(e)
                 }
                 ::futures::__rt::std::result::Result::Ok(::futures::Async::NotReady) => {}
@@ -48,7 +149,8 @@ macro_rules! await_item {
 
             yield ::futures::Async::NotReady
         }
-    })
+    });
+    ($e:expr) => (__await_context!(await_item, await_item!(@__context $e)))
 }
 
 // TODO: This macro needs to use an extra temporary variable because of
@@ -56,8 +158,145 @@ macro_rules! await_item {
 // inside the yield expression
 #[macro_export]
 macro_rules! stream_yield {
-    ($e:expr) => ({
+    (@__context $e:expr) => ({
         let e = $e;
+        ::futures::__rt::record_await(file!(), line!());
         yield ::futures::Async::Ready(e)
-    })
+    });
+    ($e:expr) => (__await_context!(stream_yield, stream_yield!(@__context $e)))
+}
+
+/// Registers a closure to run if the enclosing async function or block is
+/// dropped before it completes.
+///
+/// The closure runs synchronously when the future or stream is dropped while
+/// it's suspended, for example at an `await!` or `stream_yield!`. It does not
+/// run if the function runs to completion or leaves the enclosing scope
+/// normally. This must be used in statement position, and the closure stays
+/// registered until the end of the enclosing block.
+#[macro_export]
+macro_rules! on_cancel {
+    (@__context $e:expr) => (
+        let __on_cancel = ::futures::__rt::on_cancel($e);
+    );
+    ($e:expr) => (on_cancel!(@__context $e);)
+}
+
+/// Yields control back to the executor once.
+///
+/// The current task is notified first so it's polled again soon, which gives
+/// other tasks a chance to run in the meantime. This is useful in long running
+/// loops which otherwise rarely need to wait on anything.
+#[macro_export]
+macro_rules! yield_now {
+    (@__context) => ({
+        ::futures::task::current().notify();
+        ::futures::__rt::record_await(file!(), line!());
+        yield ::futures::Async::NotReady
+    });
+    () => (__await_context!(yield_now, yield_now!(@__context)))
+}
+
+/// Returns a handle to the task currently polling this async function.
+///
+/// This is the same as `futures::task::current()`, and like it panics if
+/// called outside of a task. It's useful together with `await_poll!` to
+/// arrange for the task to be woken up from a custom event source.
+#[macro_export]
+macro_rules! current_task {
+    () => (::futures::task::current())
+}
+
+/// Waits on a poll function inline.
+///
+/// The closure is evaluated repeatedly, yielding back to the executor each
+/// time it returns `NotReady`, until it returns a value or an error. This
+/// allows low-level readiness logic to be written directly inside of an async
+/// function instead of in a separate `Future` implementation. As with any
+/// future, the closure must arrange for the current task to be notified
+/// before returning `NotReady`.
+///
+/// ```ignore
+/// let n = await_poll!(|| {
+///     match source.try_recv() {
+///         Some(n) => Ok(Async::Ready(n)),
+///         None => {
+///             source.register(current_task!());
+///             Ok(Async::NotReady)
+///         }
+///     }
+/// })?;
+/// ```
+#[macro_export]
+macro_rules! await_poll {
+    (@__context @__cancel $cancel:ident, $e:expr) => (
+        await!(@__context @__cancel $cancel, ::futures::future::poll_fn($e))
+    );
+    (@__context $e:expr) => (
+        await!(@__context ::futures::future::poll_fn($e))
+    );
+    ($e:expr) => (__await_context!(await_poll, await_poll!(@__context $e)))
+}
+
+/// Runs a blocking closure on a thread pool and waits for its result.
+///
+/// The closure is spawned onto the pool configured in `futures_await::blocking`
+/// so it doesn't block the event loop running this async function. It must
+/// return a `Result`, which this macro evaluates to once it has finished.
+///
+/// ```ignore
+/// let contents = await_blocking!(move || std::fs::read(path))?;
+/// ```
+#[macro_export]
+macro_rules! await_blocking {
+    (@__context @__cancel $cancel:ident, $e:expr) => (
+        await!(@__context @__cancel $cancel, ::futures::blocking::spawn($e))
+    );
+    (@__context $e:expr) => (
+        await!(@__context ::futures::blocking::spawn($e))
+    );
+    ($e:expr) => (__await_context!(await_blocking, await_blocking!(@__context $e)))
+}
+
+/// Runs a group of child futures concurrently, waiting for all of them.
+///
+/// The body is handed a `scope` to `spawn` children onto, and once it has
+/// returned the children are all driven by this async function itself rather
+/// than by an executor. The macro evaluates to the body's value after every
+/// child has finished, or to the first error from a child, in which case the
+/// remaining children are dropped and so cancelled.
+///
+/// Data to be shared with the children can be moved into the scope, and the
+/// children are then allowed to borrow it. See `futures_await::Scope` for
+/// more details.
+///
+/// ```ignore
+/// async_scope!((client, urls), |scope, &(ref client, ref urls)| {
+///     for url in urls.iter() {
+///         scope.spawn(fetch(client, url));
+///     }
+/// })?;
+/// ```
+#[macro_export]
+macro_rules! async_scope {
+    (@__context @__cancel $cancel:ident, |$scope:ident| $body:expr) => (
+        await!(@__context @__cancel $cancel,
+               ::futures::Scope::new((), |$scope, _| $body))
+    );
+    (@__context @__cancel $cancel:ident, $data:expr, |$scope:ident, $d:pat| $body:expr) => (
+        await!(@__context @__cancel $cancel,
+               ::futures::Scope::new($data, |$scope, $d| $body))
+    );
+    (@__context |$scope:ident| $body:expr) => (
+        await!(@__context ::futures::Scope::new((), |$scope, _| $body))
+    );
+    (@__context $data:expr, |$scope:ident, $d:pat| $body:expr) => (
+        await!(@__context ::futures::Scope::new($data, |$scope, $d| $body))
+    );
+    (|$scope:ident| $body:expr) => (
+        __await_context!(async_scope, async_scope!(@__context |$scope| $body))
+    );
+    ($data:expr, |$scope:ident, $d:pat| $body:expr) => (
+        __await_context!(async_scope, async_scope!(@__context $data, |$scope, $d| $body))
+    );
 }
@@ -0,0 +1,55 @@
+//! Running blocking code off of the event loop.
+//!
+//! Blocking calls, like most filesystem operations or CPU heavy work, stall
+//! every other future running on the same thread. The `await_blocking!` macro
+//! runs a closure on a thread pool instead and waits for its result, using the
+//! pool configured here. By default this is a `CpuPool` with one thread per
+//! CPU, named `futures-await-blocking-N`, which is started the first time
+//! it's needed, but it can be replaced with `set_pool`.
+
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use futures::IntoFuture;
+use futures_cpupool::{Builder, CpuFuture, CpuPool};
+
+fn slot() -> &'static Mutex<Option<CpuPool>> {
+    static INIT: Once = ONCE_INIT;
+    static mut POOL: *const Mutex<Option<CpuPool>> = 0 as *const _;
+
+    unsafe {
+        INIT.call_once(|| {
+            POOL = Box::into_raw(Box::new(Mutex::new(None)));
+        });
+        &*POOL
+    }
+}
+
+/// Returns the pool used to run blocking closures, starting the default pool
+/// if no other has been configured.
+pub fn pool() -> CpuPool {
+    slot().lock().unwrap()
+        .get_or_insert_with(|| {
+            Builder::new().name_prefix("futures-await-blocking-").create()
+        })
+        .clone()
+}
+
+/// Replaces the pool used to run blocking closures.
+///
+/// Closures which were already spawned keep running on the previous pool.
+pub fn set_pool(pool: CpuPool) {
+    *slot().lock().unwrap() = Some(pool);
+}
+
+/// Runs `f` on the blocking pool, returning a future for its result.
+///
+/// This is what `await_blocking!` uses under the hood.
+pub fn spawn<F, R>(f: F) -> CpuFuture<R::Item, R::Error>
+    where F: FnOnce() -> R + Send + 'static,
+          R: IntoFuture + 'static,
+          R::Future: Send + 'static,
+          R::Item: Send + 'static,
+          R::Error: Send + 'static,
+{
+    pool().spawn_fn(f)
+}
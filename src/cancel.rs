@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::task::AtomicTask;
+
+/// The error a cancelled async function finishes with.
+///
+/// Functions tagged with `#[async(cancellable)]` return a `CancelHandle`
+/// alongside their future. Once the handle is triggered the function returns
+/// this error, converted into its own error type, from the `await!` or async
+/// `for` loop it's suspended at or the next one it reaches.
+///
+/// This means that the error type of a cancellable function must implement
+/// `From<Cancelled>`, although the futures and streams it waits on don't need
+/// to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("async function was cancelled")
+    }
+}
+
+impl Error for Cancelled {
+    fn description(&self) -> &str {
+        "async function was cancelled"
+    }
+}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, cancelled)
+    }
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    task: AtomicTask,
+}
+
+/// A handle used to cancel the future returned from an
+/// `#[async(cancellable)]` function.
+#[derive(Clone)]
+pub struct CancelHandle {
+    inner: Arc<Inner>,
+}
+
+impl CancelHandle {
+    /// Requests cancellation of the associated future.
+    ///
+    /// The future is woken up to notice, and the function then returns
+    /// `Cancelled` from the `await!` it's suspended at or the next one it
+    /// reaches.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.task.notify();
+    }
+}
+
+impl fmt::Debug for CancelHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelHandle")
+            .field("cancelled", &self.inner.cancelled.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+/// The half of a cancellation flag held by the generator.
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    /// Returns whether cancellation was requested, resetting the flag.
+    pub fn take(&self) -> bool {
+        self.inner.cancelled.swap(false, Ordering::SeqCst)
+    }
+
+    /// Arranges for the current task to be woken when cancelled.
+    pub fn register(&self) {
+        self.inner.task.register();
+    }
+}
+
+pub fn cancel_pair() -> (CancelHandle, CancelToken) {
+    let inner = Arc::new(Inner {
+        cancelled: AtomicBool::new(false),
+        task: AtomicTask::new(),
+    });
+    (CancelHandle { inner: inner.clone() }, CancelToken { inner })
+}
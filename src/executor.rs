@@ -0,0 +1,331 @@
+//! A lightweight single-threaded executor.
+//!
+//! `LocalPool` runs any number of futures on the thread which owns it, so
+//! unlike most executors the futures don't need to be `Send`. Futures are
+//! spawned onto it with `spawn_local`, and it's then driven with `block_on`,
+//! `run` or `run_until_idle`.
+//!
+//! The `block_on` and `spawn_local` functions at the root of this module are
+//! shorthands for running a future on a fresh pool and for spawning onto the
+//! pool which is currently running, respectively. This module also reexports
+//! everything from `futures::executor`.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future};
+
+pub use futures::executor::*;
+
+/// The notification id of the future passed to `block_on`.
+const ROOT: usize = !0;
+
+type Task = Spawn<Box<Future<Item = (), Error = ()>>>;
+
+thread_local!(static CURRENT: RefCell<Option<LocalSpawner>> = RefCell::new(None));
+
+/// A single-threaded pool of futures.
+///
+/// None of the futures spawned onto the pool run until one of `block_on`,
+/// `run` or `run_until_idle` is called, and they all run on that thread.
+pub struct LocalPool {
+    tasks: Rc<RefCell<Tasks>>,
+    wakeups: Arc<Wakeups>,
+}
+
+/// A handle for spawning futures onto a `LocalPool`.
+///
+/// Unlike the pool itself this can be cloned and moved into the futures
+/// running on it.
+#[derive(Clone)]
+pub struct LocalSpawner {
+    tasks: Weak<RefCell<Tasks>>,
+    wakeups: Arc<Wakeups>,
+}
+
+struct Tasks {
+    slots: Vec<Option<Task>>,
+    free: Vec<usize>,
+    alive: usize,
+}
+
+/// The ids of the futures which have been woken up, shared with their tasks.
+struct Wakeups {
+    ready: Mutex<Vec<usize>>,
+    thread: Thread,
+}
+
+impl Notify for Wakeups {
+    fn notify(&self, id: usize) {
+        self.ready.lock().unwrap().push(id);
+        self.thread.unpark();
+    }
+}
+
+impl Wakeups {
+    fn take(&self) -> Vec<usize> {
+        mem::replace(&mut *self.ready.lock().unwrap(), Vec::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.lock().unwrap().is_empty()
+    }
+}
+
+impl LocalPool {
+    /// Creates a new, empty pool owned by the current thread.
+    pub fn new() -> LocalPool {
+        LocalPool {
+            tasks: Rc::new(RefCell::new(Tasks {
+                slots: Vec::new(),
+                free: Vec::new(),
+                alive: 0,
+            })),
+            wakeups: Arc::new(Wakeups {
+                ready: Mutex::new(Vec::new()),
+                thread: thread::current(),
+            }),
+        }
+    }
+
+    /// Returns a handle which can be used to spawn futures onto this pool.
+    pub fn spawner(&self) -> LocalSpawner {
+        LocalSpawner {
+            tasks: Rc::downgrade(&self.tasks),
+            wakeups: self.wakeups.clone(),
+        }
+    }
+
+    /// Spawns a future onto this pool.
+    pub fn spawn_local<F>(&self, future: F)
+        where F: Future<Item = (), Error = ()> + 'static,
+    {
+        self.spawner().spawn_local(future)
+    }
+
+    /// Runs `future` to completion on the current thread, running the
+    /// futures spawned onto this pool at the same time.
+    ///
+    /// Any spawned futures which haven't finished by the time `future` does
+    /// stay in the pool, to be run the next time it's driven.
+    pub fn block_on<F: Future>(&mut self, future: F) -> Result<F::Item, F::Error> {
+        self.block_on_until(future, None).unwrap()
+    }
+
+    /// Like `block_on`, but gives up and returns `None` if `future` hasn't
+    /// completed within `timeout`.
+    pub fn block_on_timeout<F: Future>(&mut self, future: F, timeout: Duration)
+        -> Option<Result<F::Item, F::Error>>
+    {
+        self.block_on_until(future, Some(Instant::now() + timeout))
+    }
+
+    /// Runs the futures spawned onto this pool until they've all completed.
+    pub fn run(&mut self) {
+        let _enter = self.enter();
+        loop {
+            while !self.wakeups.is_empty() {
+                self.poll_ready();
+            }
+            if self.tasks.borrow().alive == 0 {
+                return
+            }
+            thread::park();
+        }
+    }
+
+    /// Runs the futures spawned onto this pool until none of them can make
+    /// any more progress, without blocking the thread.
+    pub fn run_until_idle(&mut self) {
+        let _enter = self.enter();
+        while !self.wakeups.is_empty() {
+            self.poll_ready();
+        }
+    }
+
+    fn block_on_until<F: Future>(&mut self, future: F, deadline: Option<Instant>)
+        -> Option<Result<F::Item, F::Error>>
+    {
+        let _enter = self.enter();
+        let mut root = spawn(future);
+        self.wakeups.notify(ROOT);
+        loop {
+            if self.poll_ready() {
+                match root.poll_future_notify(&self.wakeups, ROOT) {
+                    Ok(Async::Ready(item)) => return Some(Ok(item)),
+                    Ok(Async::NotReady) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            // The deadline is checked on every turn, otherwise a future which
+            // keeps waking itself up would never time out.
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            if !self.wakeups.is_empty() {
+                continue
+            }
+            match remaining {
+                Some(remaining) => thread::park_timeout(remaining),
+                None => thread::park(),
+            }
+        }
+    }
+
+    /// Polls each spawned future which has been woken up since the last call,
+    /// returning whether the root future was woken up too.
+    fn poll_ready(&self) -> bool {
+        let mut root = false;
+        for id in self.wakeups.take() {
+            if id == ROOT {
+                root = true;
+                continue
+            }
+            // The future is taken out of its slot while it's polled so it can
+            // spawn more futures onto the pool.
+            let task = self.tasks.borrow_mut().slots.get_mut(id).and_then(|slot| slot.take());
+            let task = match task {
+                Some(task) => task,
+                None => continue,
+            };
+            let mut running = Running { tasks: &self.tasks, id: id, task: Some(task) };
+            let ready = match running.task.as_mut().unwrap().poll_future_notify(&self.wakeups, id) {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) | Err(()) => true,
+            };
+            if !ready {
+                self.tasks.borrow_mut().slots[id] = running.task.take();
+            }
+        }
+        root
+    }
+
+    /// Makes this pool the target of the `spawn_local` function until the
+    /// returned guard is dropped.
+    fn enter(&self) -> Enter {
+        let prev = CURRENT.with(|current| {
+            mem::replace(&mut *current.borrow_mut(), Some(self.spawner()))
+        });
+        Enter { prev: prev }
+    }
+}
+
+impl Default for LocalPool {
+    fn default() -> LocalPool {
+        LocalPool::new()
+    }
+}
+
+impl fmt::Debug for LocalPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalPool")
+            .field("tasks", &self.tasks.borrow().alive)
+            .finish()
+    }
+}
+
+/// A spawned future which has been taken out of its slot to be polled.
+///
+/// Unless it's put back the future is dropped along with this, freeing its
+/// slot. That includes when polling it panics, so that a pool which is run
+/// again after the panic was caught doesn't wait on the future forever.
+struct Running<'a> {
+    tasks: &'a RefCell<Tasks>,
+    id: usize,
+    task: Option<Task>,
+}
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            drop(task);
+            let mut tasks = self.tasks.borrow_mut();
+            tasks.free.push(self.id);
+            tasks.alive -= 1;
+        }
+    }
+}
+
+struct Enter {
+    prev: Option<LocalSpawner>,
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+impl LocalSpawner {
+    /// Spawns a future onto the pool this handle belongs to.
+    ///
+    /// If the pool has already been dropped the future is dropped as well.
+    pub fn spawn_local<F>(&self, future: F)
+        where F: Future<Item = (), Error = ()> + 'static,
+    {
+        let tasks = match self.tasks.upgrade() {
+            Some(tasks) => tasks,
+            None => return,
+        };
+        let task = spawn(Box::new(future) as Box<Future<Item = (), Error = ()>>);
+        let id = {
+            let mut tasks = tasks.borrow_mut();
+            tasks.alive += 1;
+            match tasks.free.pop() {
+                Some(id) => {
+                    tasks.slots[id] = Some(task);
+                    id
+                }
+                None => {
+                    tasks.slots.push(Some(task));
+                    tasks.slots.len() - 1
+                }
+            }
+        };
+        self.wakeups.notify(id);
+    }
+}
+
+impl fmt::Debug for LocalSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalSpawner").finish()
+    }
+}
+
+/// Runs `future` to completion on a new `LocalPool`.
+///
+/// Futures spawned with `spawn_local` while it's running are run on the same
+/// pool, and dropped if they haven't finished by the time `future` has.
+pub fn block_on<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    LocalPool::new().block_on(future)
+}
+
+/// Spawns a future onto the `LocalPool` which is running on this thread.
+///
+/// # Panics
+///
+/// This function panics if it's not called from within a future being run by
+/// a `LocalPool`.
+pub fn spawn_local<F>(future: F)
+    where F: Future<Item = (), Error = ()> + 'static,
+{
+    CURRENT.with(|current| {
+        match *current.borrow() {
+            Some(ref spawner) => spawner.spawn_local(future),
+            None => panic!("`spawn_local` called outside of a local executor"),
+        }
+    })
+}
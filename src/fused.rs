@@ -0,0 +1,23 @@
+use futures::{Future, Stream};
+
+/// A future which tracks whether it has already completed.
+///
+/// Futures returned from unboxed `#[async]` functions and `async_block!`
+/// implement this trait. Once such a future has resolved, either successfully
+/// or with an error, `is_terminated` returns `true` and the future must not be
+/// polled again.
+pub trait FusedFuture: Future {
+    /// Returns `true` if this future has already completed.
+    fn is_terminated(&self) -> bool;
+}
+
+/// A stream which tracks whether it has already finished.
+///
+/// Streams returned from unboxed `#[async_stream]` functions and
+/// `async_stream_block!` implement this trait. Once such a stream has
+/// returned `None` or an error `is_terminated` returns `true`, and polling it
+/// again will continue to return `None`.
+pub trait FusedStream: Stream {
+    /// Returns `true` if this stream has already finished.
+    fn is_terminated(&self) -> bool;
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::Duration;
+
+/// Callbacks invoked as instrumented async functions are polled.
+///
+/// Functions tagged with `#[async(instrument)]` or
+/// `#[async_stream(instrument)]` report to the instrument registered with
+/// `set_instrument`, passing their name such as `#[async] fn foo`. All of the
+/// callbacks default to doing nothing.
+pub trait Instrument: Send + Sync {
+    /// Called before the function is polled for the first time.
+    fn on_first_poll(&self, _name: &'static str) {}
+
+    /// Called after each poll of the function with the time it took.
+    fn on_poll(&self, _name: &'static str, _duration: Duration) {}
+
+    /// Called when a poll leaves the function suspended, or for streams
+    /// after it produces an item.
+    fn on_yield(&self, _name: &'static str) {}
+
+    /// Called when the function finishes.
+    fn on_complete(&self, _name: &'static str) {}
+}
+
+struct NopInstrument;
+
+impl Instrument for NopInstrument {}
+
+static STATE: AtomicUsize = ATOMIC_USIZE_INIT;
+static mut INSTRUMENT: &'static Instrument = &NopInstrument;
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+/// Registers the global instrument.
+///
+/// This can only be called once, later calls return an error.
+pub fn set_instrument<I>(instrument: I) -> Result<(), SetInstrumentError>
+    where I: Instrument + 'static,
+{
+    match STATE.compare_and_swap(UNINITIALIZED, INITIALIZING, Ordering::SeqCst) {
+        UNINITIALIZED => {
+            unsafe {
+                INSTRUMENT = &*Box::into_raw(Box::new(instrument));
+            }
+            STATE.store(INITIALIZED, Ordering::SeqCst);
+            Ok(())
+        }
+        _ => Err(SetInstrumentError(())),
+    }
+}
+
+/// Returns the global instrument.
+pub fn get() -> &'static Instrument {
+    if STATE.load(Ordering::SeqCst) != INITIALIZED {
+        return &NopInstrument
+    }
+    unsafe { INSTRUMENT }
+}
+
+/// The error returned from `set_instrument` if it was already called.
+#[derive(Debug)]
+pub struct SetInstrumentError(());
+
+impl fmt::Display for SetInstrumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for SetInstrumentError {
+    fn description(&self) -> &str {
+        "an instrument has already been registered"
+    }
+}
@@ -19,13 +19,47 @@
 extern crate futures_await_async_macro as async_macro;
 extern crate futures_await_await_macro as await_macro;
 extern crate futures;
+extern crate futures_cpupool;
+#[cfg(feature = "tokio")]
+extern crate tokio_core;
+#[cfg(feature = "mock")]
+extern crate tokio_io;
 
 pub use futures::*;
 
+mod cancel;
+mod fused;
+mod instrument;
+mod scope;
+mod small;
+mod unwind;
+
+pub use cancel::{CancelHandle, Cancelled};
+pub use fused::{FusedFuture, FusedStream};
+pub use instrument::{set_instrument, Instrument, SetInstrumentError};
+pub use scope::{Scope, Spawner};
+pub use small::SmallFuture;
+pub use unwind::PanicPayload;
+
+pub mod blocking;
+pub mod executor;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod watchdog;
+
 pub mod prelude {
     pub use futures::prelude::*;
     pub use async_macro::{async, async_stream, async_block, async_stream_block};
-    pub use await_macro::{await, stream_yield, await_item};
+    pub use async_macro::{async_main, async_test, maybe_async};
+    pub use async_macro::{async_closure, async_stream_closure};
+    pub use await_macro::{await, stream_yield, await_item, on_cancel, yield_now};
+    pub use await_macro::{async_scope, await_blocking, await_poll, current_task};
+    #[doc(hidden)]
+    pub use await_macro::__await_context;
 }
 
 /// A hidden module that's the "runtime support" for the async/await syntax.
@@ -41,25 +75,23 @@ pub mod prelude {
 pub mod __rt {
     pub extern crate std;
     pub use std::ops::Generator;
+    pub use cancel::{cancel_pair, CancelToken};
 
     use futures::Poll;
     use futures::{Future, Async, Stream};
+    use std::cell::Cell;
     use std::ops::GeneratorState;
     use std::marker::PhantomData;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    use std::fmt::Debug;
+    use std::process;
+    use std::time::{Duration, Instant};
+    use std::panic::{self, AssertUnwindSafe};
 
-    pub trait MyFuture<T: IsResult>: Future<Item=T::Ok, Error = T::Err> {}
-
-    pub trait MyStream<T, U: IsResult<Ok=()>>: Stream<Item=T, Error=U::Err> {}
-
-    impl<F, T> MyFuture<T> for F
-        where F: Future<Item = T::Ok, Error = T::Err > + ?Sized,
-              T: IsResult
-    {}
+    use executor::{self, LocalPool};
 
-    impl<F, T, U> MyStream<T, U> for F
-        where F: Stream<Item = T, Error = U::Err> + ?Sized,
-              U: IsResult<Ok=()>
-    {}
+    use fused::{FusedFuture, FusedStream};
+    use unwind::PanicPayload;
 
     #[rustc_on_unimplemented = "async functions must return a `Result` or \
                                 a typedef of `Result`"]
@@ -82,31 +114,211 @@ pub mod __rt {
     ///
     /// This is the translation layer from the generator/coroutine protocol to
     /// the futures protocol.
-    struct GenFuture<T>(T);
+    #[must_use = "futures do nothing unless polled"]
+    pub struct GenFuture<T> {
+        gen: T,
+        header: Header,
+        done: bool,
+    }
 
     /// Small shim to translate from a generator to a stream.
-    struct GenStream<U, T> {
+    #[must_use = "streams do nothing unless polled"]
+    pub struct GenStream<U, T> {
         gen: T,
+        header: Header,
         done: bool,
         phantom: PhantomData<U>,
     }
 
+    /// Static information about where a generator came from.
+    pub struct Meta {
+        /// Describes the function or block for use in diagnostics, for
+        /// example `#[async] fn foo`.
+        pub name: &'static str,
+        /// Whether to report polls to the global `Instrument`.
+        pub instrument: bool,
+        /// How many times the generator may make progress without
+        /// suspending in a single poll before it's forced to yield.
+        pub budget: Option<u32>,
+    }
+
+    /// State shared by futures and streams for the runtime support around
+    /// resuming their generator.
+    struct Header {
+        id: usize,
+        meta: &'static Meta,
+        #[cfg_attr(not(feature = "trace"), allow(dead_code))]
+        site: Site,
+        polled: bool,
+    }
+
+    impl Header {
+        fn new(meta: &'static Meta) -> Header {
+            Header { id: next_id(), meta, site: Site::default(), polled: false }
+        }
+    }
+
     /// Uninhabited type to allow `await!` to work across both `async` and
     /// `async_stream`.
     pub enum Mu {}
 
-    pub fn gen<T>(gen: T) -> impl MyFuture<T::Return>
+    static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    thread_local! {
+        // Identifier of the generator currently being resumed on this thread,
+        // or 0 if there isn't one.
+        static CURRENT: Cell<usize> = Cell::new(0);
+    }
+
+    fn next_id() -> usize {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn current_id() -> usize {
+        CURRENT.try_with(|c| c.get()).unwrap_or(0)
+    }
+
+    /// Location of the `await!` a generator is suspended at, which is only
+    /// tracked with the `trace` feature.
+    #[cfg(feature = "trace")]
+    type Site = Option<(&'static str, u32)>;
+    #[cfg(not(feature = "trace"))]
+    type Site = ();
+
+    thread_local! {
+        // Location of the most recent `await!` reached on this thread, used by
+        // watchdogs to report where a future is parked.
+        static AWAIT_SITE: Cell<Option<(&'static str, u32)>> = Cell::new(None);
+    }
+
+    thread_local! {
+        // Remaining budget of the generator currently being resumed, if it
+        // has one.
+        static BUDGET: Cell<Option<u32>> = Cell::new(None);
+    }
+
+    /// Called each time `await!` and friends find their value ready without
+    /// suspending, returning whether the current generator has exhausted its
+    /// budget and needs to yield back to the executor.
+    pub fn consume_budget() -> bool {
+        BUDGET.with(|budget| match budget.get() {
+            Some(n) if n <= 1 => {
+                budget.set(None);
+                true
+            }
+            Some(n) => {
+                budget.set(Some(n - 1));
+                false
+            }
+            None => false,
+        })
+    }
+
+    /// Called by `await!` and friends with their location before suspending.
+    pub fn record_await(file: &'static str, line: u32) {
+        AWAIT_SITE.with(|site| site.set(Some((file, line))));
+        #[cfg(feature = "trace")]
+        ::trace::set_site(file, line);
+    }
+
+    /// Called by `await!` and friends before they first poll the future
+    /// they're waiting on, returning whether they should suspend anyway. See
+    /// the `testing` module, without which this is always `false`.
+    #[cfg(feature = "testing")]
+    pub fn inject_not_ready() -> bool {
+        ::testing::inject(AWAIT_SITE.with(|site| site.get()))
+    }
+    #[cfg(not(feature = "testing"))]
+    #[inline(always)]
+    pub fn inject_not_ready() -> bool {
+        false
+    }
+
+    /// Replaces the location of the most recent `await!`, returning the
+    /// previous one.
+    pub fn replace_await_site(new: Option<(&'static str, u32)>)
+        -> Option<(&'static str, u32)>
+    {
+        AWAIT_SITE.with(|site| site.replace(new))
+    }
+
+    /// Resumes `gen`, recording it as the current generator while it runs.
+    fn resume<T: Generator>(header: &mut Header, gen: &mut T)
+        -> GeneratorState<T::Yield, T::Return>
+    {
+        struct Reset(usize, Option<u32>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                CURRENT.with(|c| c.set(self.0));
+                BUDGET.with(|b| b.set(self.1));
+            }
+        }
+
+        let name = header.meta.name;
+        let _reset = Reset(
+            CURRENT.with(|c| c.replace(header.id)),
+            BUDGET.with(|b| b.replace(header.meta.budget)),
+        );
+        #[cfg(feature = "trace")]
+        let _frame = ::trace::enter(name, &mut header.site);
+
+        if !header.meta.instrument {
+            return unsafe { gen.resume() }
+        }
+
+        let instrument = ::instrument::get();
+        if !header.polled {
+            header.polled = true;
+            instrument.on_first_poll(name);
+        }
+        let start = Instant::now();
+        let state = unsafe { gen.resume() };
+        instrument.on_poll(name, start.elapsed());
+        match state {
+            GeneratorState::Yielded(_) => instrument.on_yield(name),
+            GeneratorState::Complete(_) => instrument.on_complete(name),
+        }
+        state
+    }
+
+    /// Guard created by `on_cancel!`.
+    ///
+    /// Locals of a generator are dropped either while it's being resumed, as
+    /// it runs to completion or leaves a scope, or when the generator itself is
+    /// dropped while it's suspended. Only the latter runs the closure, which
+    /// is told apart by checking which generator is being resumed.
+    pub struct OnCancel<F: FnOnce()> {
+        id: usize,
+        f: Option<F>,
+    }
+
+    pub fn on_cancel<F: FnOnce()>(f: F) -> OnCancel<F> {
+        OnCancel { id: current_id(), f: Some(f) }
+    }
+
+    impl<F: FnOnce()> Drop for OnCancel<F> {
+        fn drop(&mut self) {
+            if current_id() != self.id {
+                if let Some(f) = self.f.take() {
+                    f();
+                }
+            }
+        }
+    }
+
+    pub fn gen<T>(meta: &'static Meta, gen: T) -> GenFuture<T>
         where T: Generator<Yield = Async<Mu>>,
               T::Return: IsResult,
     {
-        GenFuture(gen)
+        GenFuture { gen, header: Header::new(meta), done: false }
     }
 
-    pub fn gen_stream<T, U>(gen: T) -> impl MyStream<U, T::Return>
+    pub fn gen_stream<T, U>(meta: &'static Meta, gen: T) -> GenStream<U, T>
         where T: Generator<Yield = Async<U>>,
               T::Return: IsResult<Ok = ()>,
     {
-        GenStream { gen, done: false, phantom: PhantomData }
+        GenStream { gen, header: Header::new(meta), done: false, phantom: PhantomData }
     }
 
     impl<T> Future for GenFuture<T>
@@ -117,17 +329,31 @@ pub mod __rt {
         type Error = <T::Return as IsResult>::Err;
 
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            match unsafe { self.0.resume() } {
+            if self.done {
+                panic!("`{}` polled after completion", self.header.meta.name)
+            }
+            match resume(&mut self.header, &mut self.gen) {
                 GeneratorState::Yielded(Async::NotReady)
                     => Ok(Async::NotReady),
                 GeneratorState::Yielded(Async::Ready(mu))
                     => match mu {},
-                GeneratorState::Complete(e)
-                    => e.into_result().map(Async::Ready),
+                GeneratorState::Complete(e) => {
+                    self.done = true;
+                    e.into_result().map(Async::Ready)
+                }
             }
         }
     }
 
+    impl<T> FusedFuture for GenFuture<T>
+        where T: Generator<Yield = Async<Mu>>,
+              T::Return: IsResult,
+    {
+        fn is_terminated(&self) -> bool {
+            self.done
+        }
+    }
+
     impl<U, T> Stream for GenStream<U, T>
         where T: Generator<Yield = Async<U>>,
               T::Return: IsResult<Ok = ()>,
@@ -137,7 +363,7 @@ pub mod __rt {
 
         fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
             if self.done { return Ok(Async::Ready(None)) }
-            match unsafe { self.gen.resume() } {
+            match resume(&mut self.header, &mut self.gen) {
                 GeneratorState::Yielded(Async::Ready(e)) => {
                     Ok(Async::Ready(Some(e)))
                 }
@@ -151,4 +377,157 @@ pub mod __rt {
             }
         }
     }
+
+    impl<U, T> FusedStream for GenStream<U, T>
+        where T: Generator<Yield = Async<U>>,
+              T::Return: IsResult<Ok = ()>,
+    {
+        fn is_terminated(&self) -> bool {
+            self.done
+        }
+    }
+
+    /// Wrapper around `GenFuture` which catches panics from the generator.
+    #[must_use = "futures do nothing unless polled"]
+    pub struct CatchUnwind<T>(GenFuture<T>);
+
+    /// Wrapper around `GenStream` which catches panics from the generator.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct CatchUnwindStream<U, T>(GenStream<U, T>);
+
+    pub fn gen_catch_unwind<T>(meta: &'static Meta, gen: T) -> CatchUnwind<T>
+        where T: Generator<Yield = Async<Mu>>,
+              T::Return: IsResult,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        CatchUnwind(self::gen(meta, gen))
+    }
+
+    pub fn gen_stream_catch_unwind<T, U>(meta: &'static Meta, gen: T)
+        -> CatchUnwindStream<U, T>
+        where T: Generator<Yield = Async<U>>,
+              T::Return: IsResult<Ok = ()>,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        CatchUnwindStream(gen_stream(meta, gen))
+    }
+
+    impl<T> Future for CatchUnwind<T>
+        where T: Generator<Yield = Async<Mu>>,
+              T::Return: IsResult,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        type Item = <T::Return as IsResult>::Ok;
+        type Error = <T::Return as IsResult>::Err;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            // A generator which panicked can't be resumed again, so it's
+            // considered to have completed with the panic as its error.
+            if self.0.done {
+                return self.0.poll()
+            }
+            let inner = &mut self.0;
+            match panic::catch_unwind(AssertUnwindSafe(|| inner.poll())) {
+                Ok(poll) => poll,
+                Err(payload) => {
+                    inner.done = true;
+                    Err(PanicPayload::new(payload).into())
+                }
+            }
+        }
+    }
+
+    impl<T> FusedFuture for CatchUnwind<T>
+        where T: Generator<Yield = Async<Mu>>,
+              T::Return: IsResult,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        fn is_terminated(&self) -> bool {
+            self.0.done
+        }
+    }
+
+    impl<U, T> Stream for CatchUnwindStream<U, T>
+        where T: Generator<Yield = Async<U>>,
+              T::Return: IsResult<Ok = ()>,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        type Item = U;
+        type Error = <T::Return as IsResult>::Err;
+
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            let inner = &mut self.0;
+            match panic::catch_unwind(AssertUnwindSafe(|| inner.poll())) {
+                Ok(poll) => poll,
+                Err(payload) => {
+                    inner.done = true;
+                    Err(PanicPayload::new(payload).into())
+                }
+            }
+        }
+    }
+
+    impl<U, T> FusedStream for CatchUnwindStream<U, T>
+        where T: Generator<Yield = Async<U>>,
+              T::Return: IsResult<Ok = ()>,
+              <T::Return as IsResult>::Err: From<PanicPayload>,
+    {
+        fn is_terminated(&self) -> bool {
+            self.0.done
+        }
+    }
+
+    /// Drives the future returned from an `#[async_test]` function on a
+    /// `LocalPool`, panicking if it fails or doesn't finish in time.
+    pub fn run_test<F>(future: F, timeout_millis: Option<u64>)
+        where F: Future,
+              F::Error: Debug,
+    {
+        let mut pool = LocalPool::new();
+        let result = match timeout_millis {
+            Some(millis) => {
+                match pool.block_on_timeout(future, Duration::from_millis(millis)) {
+                    Some(result) => result,
+                    None => panic!("async test timed out after {}ms", millis),
+                }
+            }
+            None => pool.block_on(future),
+        };
+        if let Err(e) = result {
+            panic!("async test failed with error: {:?}", e)
+        }
+    }
+
+    /// Runs the future returned from an `#[async_main]` function to
+    /// completion on a `LocalPool`.
+    pub fn run_main<F>(future: F)
+        where F: Future<Item = ()>,
+              F::Error: Debug,
+    {
+        if let Err(e) = executor::block_on(future) {
+            main_failed(e)
+        }
+    }
+
+    /// Runs an `#[async_main(tokio)]` function on a new `tokio-core` event
+    /// loop, passing it a handle to the loop.
+    #[cfg(feature = "tokio")]
+    pub fn run_main_tokio<F, R>(main: F)
+        where F: FnOnce(::tokio_core::reactor::Handle) -> R,
+              R: Future<Item = ()>,
+              R::Error: Debug,
+    {
+        let mut core = ::tokio_core::reactor::Core::new()
+            .expect("failed to create event loop");
+        let future = main(core.handle());
+        if let Err(e) = core.run(future) {
+            main_failed(e)
+        }
+    }
+
+    // Mirrors what the standard library does when `main` returns an error.
+    fn main_failed<E: Debug>(e: E) -> ! {
+        eprintln!("Error: {:?}", e);
+        process::exit(1)
+    }
 }
@@ -0,0 +1,381 @@
+//! A deterministic runtime for testing async code.
+//!
+//! `MockRuntime` runs futures on a `LocalPool` together with a virtual clock.
+//! Time only moves forward when every future on the runtime is idle, and then
+//! it jumps straight to the next timer which is due, so tests of timeouts and
+//! retries run instantly and always see the same interleaving. Timers are
+//! created with `sleep` and `timeout` from this module, which must be polled
+//! from within a `MockRuntime`.
+//!
+//! The `duplex` function creates a pair of connected in-memory streams
+//! implementing `AsyncRead` and `AsyncWrite`, standing in for a network
+//! connection.
+//!
+//! This module is only available with the `mock` feature of this crate.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use executor::LocalPool;
+
+thread_local!(static CLOCK: RefCell<Option<Rc<Clock>>> = RefCell::new(None));
+
+struct Clock {
+    now: Cell<Duration>,
+    /// The deadline of each pending timer and the task waiting on it, keyed
+    /// by an id handed out to each `Sleep` when it's first polled.
+    timers: RefCell<HashMap<usize, (Duration, Task)>>,
+    next_id: Cell<usize>,
+}
+
+impl Clock {
+    /// Moves the clock forward to the earliest pending timer and wakes every
+    /// task waiting on a timer which is now due, returning whether there was
+    /// one to wake.
+    fn advance(&self) -> bool {
+        let due = {
+            let mut timers = self.timers.borrow_mut();
+            let next = match timers.values().map(|&(deadline, _)| deadline).min() {
+                Some(next) => next,
+                None => return false,
+            };
+            self.now.set(cmp::max(self.now.get(), next));
+            let now = self.now.get();
+            let ids = timers.iter()
+                .filter(|&(_, &(deadline, _))| deadline <= now)
+                .map(|(&id, _)| id)
+                .collect::<Vec<_>>();
+            ids.into_iter().map(|id| timers.remove(&id).unwrap().1).collect::<Vec<_>>()
+        };
+        for task in due {
+            task.notify();
+        }
+        true
+    }
+}
+
+fn with_clock<R, F: FnOnce(&Clock) -> R>(f: F) -> R {
+    CLOCK.with(|clock| {
+        match *clock.borrow() {
+            Some(ref clock) => f(clock),
+            None => panic!("mock timers can only be used within a `MockRuntime`"),
+        }
+    })
+}
+
+/// An executor with a virtual clock.
+pub struct MockRuntime {
+    pool: LocalPool,
+    clock: Rc<Clock>,
+}
+
+impl MockRuntime {
+    /// Creates a new runtime whose clock starts at zero.
+    pub fn new() -> MockRuntime {
+        MockRuntime {
+            pool: LocalPool::new(),
+            clock: Rc::new(Clock {
+                now: Cell::new(Duration::from_secs(0)),
+                timers: RefCell::new(HashMap::new()),
+                next_id: Cell::new(0),
+            }),
+        }
+    }
+
+    /// Returns how much virtual time has passed since this runtime was
+    /// created.
+    pub fn now(&self) -> Duration {
+        self.clock.now.get()
+    }
+
+    /// Spawns a future onto this runtime.
+    pub fn spawn_local<F>(&self, future: F)
+        where F: Future<Item = (), Error = ()> + 'static,
+    {
+        self.pool.spawn_local(future)
+    }
+
+    /// Runs `future` to completion, along with the futures spawned onto this
+    /// runtime, advancing the clock whenever they're all idle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `future` can't complete because everything is idle and there
+    /// are no timers left to fire.
+    pub fn block_on<F>(&mut self, future: F) -> Result<F::Item, F::Error>
+        where F: Future + 'static,
+    {
+        let result = Rc::new(RefCell::new(None));
+        let slot = result.clone();
+        self.pool.spawn_local(future.then(move |r| {
+            *slot.borrow_mut() = Some(r);
+            Ok(())
+        }));
+        let _enter = self.enter();
+        loop {
+            self.pool.run_until_idle();
+            if let Some(result) = result.borrow_mut().take() {
+                return result
+            }
+            if !self.clock.advance() {
+                panic!("future passed to `MockRuntime::block_on` can never \
+                        complete, all futures are idle and no timers are pending")
+            }
+        }
+    }
+
+    /// Runs the futures spawned onto this runtime, advancing the clock
+    /// whenever they're all idle, until no timers are left to fire.
+    pub fn run(&mut self) {
+        let _enter = self.enter();
+        loop {
+            self.pool.run_until_idle();
+            if !self.clock.advance() {
+                return
+            }
+        }
+    }
+
+    fn enter(&self) -> Enter {
+        let prev = CLOCK.with(|clock| {
+            mem::replace(&mut *clock.borrow_mut(), Some(self.clock.clone()))
+        });
+        Enter { prev: prev }
+    }
+}
+
+impl Default for MockRuntime {
+    fn default() -> MockRuntime {
+        MockRuntime::new()
+    }
+}
+
+impl fmt::Debug for MockRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockRuntime")
+            .field("now", &self.clock.now.get())
+            .finish()
+    }
+}
+
+struct Enter {
+    prev: Option<Rc<Clock>>,
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CLOCK.with(|clock| *clock.borrow_mut() = prev);
+    }
+}
+
+/// Returns how much virtual time has passed on the `MockRuntime` running on
+/// this thread.
+///
+/// # Panics
+///
+/// Panics if no `MockRuntime` is running on this thread.
+pub fn now() -> Duration {
+    with_clock(|clock| clock.now.get())
+}
+
+/// A future which completes once a certain amount of virtual time has passed.
+///
+/// Created by the `sleep` function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep {
+    duration: Duration,
+    /// The id of this sleep's timer and its deadline, once it has been polled.
+    timer: Option<(usize, Duration)>,
+}
+
+/// Creates a future which completes after `duration` has passed on the
+/// virtual clock, starting from when it's first polled.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep { duration: duration, timer: None }
+}
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let duration = self.duration;
+        let timer = &mut self.timer;
+        with_clock(|clock| {
+            let (id, deadline) = *timer.get_or_insert_with(|| {
+                let id = clock.next_id.get();
+                clock.next_id.set(id + 1);
+                (id, clock.now.get() + duration)
+            });
+            let mut timers = clock.timers.borrow_mut();
+            if clock.now.get() >= deadline {
+                timers.remove(&id);
+                Ok(Async::Ready(()))
+            } else {
+                // Polling again only replaces the task to wake
+                timers.insert(id, (deadline, task::current()));
+                Ok(Async::NotReady)
+            }
+        })
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // Otherwise the clock would still be moved forward to the deadline of
+        // a sleep nobody is waiting on any more
+        if let Some((id, _)) = self.timer {
+            let _ = CLOCK.try_with(|clock| {
+                if let Some(ref clock) = *clock.borrow() {
+                    clock.timers.borrow_mut().remove(&id);
+                }
+            });
+        }
+    }
+}
+
+/// A future which gives up on another if it takes too long.
+///
+/// Created by the `timeout` function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Waits on `future` for at most `duration` of virtual time, resolving to
+/// `None` if it didn't complete in time.
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout { future: future, sleep: sleep(duration) }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Item = Option<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Option<F::Item>, F::Error> {
+        if let Async::Ready(item) = self.future.poll()? {
+            return Ok(Async::Ready(Some(item)))
+        }
+        match self.sleep.poll() {
+            Ok(Async::Ready(())) => Ok(Async::Ready(None)),
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// One direction of a duplex stream.
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    /// Set once the writing end has been shut down or dropped.
+    closed: bool,
+    /// Set once the reading end has been dropped.
+    hung_up: bool,
+    reader: Option<Task>,
+}
+
+/// One end of an in-memory duplex stream.
+///
+/// Created by the `duplex` function. Anything written to one end can be read
+/// from the other, and once one end is shut down or dropped the other reads
+/// end-of-file after draining what was already written.
+pub struct MockStream {
+    read: Rc<RefCell<Pipe>>,
+    write: Rc<RefCell<Pipe>>,
+}
+
+/// Creates a pair of connected in-memory streams.
+pub fn duplex() -> (MockStream, MockStream) {
+    let a = Rc::new(RefCell::new(Pipe::default()));
+    let b = Rc::new(RefCell::new(Pipe::default()));
+    let left = MockStream { read: a.clone(), write: b.clone() };
+    let right = MockStream { read: b, write: a };
+    (left, right)
+}
+
+impl MockStream {
+    fn close(&mut self) {
+        let mut pipe = self.write.borrow_mut();
+        pipe.closed = true;
+        if let Some(task) = pipe.reader.take() {
+            task.notify();
+        }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.borrow_mut();
+        if pipe.buf.is_empty() {
+            if pipe.closed || buf.is_empty() {
+                return Ok(0)
+            }
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into())
+        }
+        let n = cmp::min(buf.len(), pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl AsyncRead for MockStream {}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.borrow_mut();
+        if pipe.hung_up {
+            return Err(io::ErrorKind::BrokenPipe.into())
+        }
+        if pipe.closed {
+            return Err(io::Error::new(io::ErrorKind::Other, "stream has been shut down"))
+        }
+        pipe.buf.extend(buf);
+        if let Some(task) = pipe.reader.take() {
+            task.notify();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.close();
+        self.read.borrow_mut().hung_up = true;
+    }
+}
+
+impl fmt::Debug for MockStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockStream")
+            .field("buffered", &self.read.borrow().buf.len())
+            .finish()
+    }
+}
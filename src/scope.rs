@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+
+use futures::{Async, Future, IntoFuture, Poll};
+
+type Child<'a, E> = Box<Future<Item = (), Error = E> + 'a>;
+
+/// Spawns child futures into an enclosing `Scope`.
+///
+/// This is the `scope` argument handed to the body of `async_scope!`. Children
+/// may borrow the scope's data for `'a`, but not anything shorter lived.
+pub struct Spawner<'a, E> {
+    children: RefCell<Vec<Child<'a, E>>>,
+}
+
+impl<'a, E> Spawner<'a, E> {
+    /// Adds a child to the scope.
+    ///
+    /// The child is driven alongside all the others once the scope's body has
+    /// returned, and its result is discarded. If it fails the scope fails with
+    /// the same error, dropping every child which hasn't finished yet.
+    pub fn spawn<F>(&self, f: F)
+        where F: IntoFuture<Error = E>,
+              F::Future: 'a,
+    {
+        let child = f.into_future().map(|_| ());
+        self.children.borrow_mut().push(Box::new(child));
+    }
+}
+
+impl<'a, E> fmt::Debug for Spawner<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("children", &self.children.borrow().len())
+            .finish()
+    }
+}
+
+/// A future driving a group of child futures to completion.
+///
+/// This is what `async_scope!` waits on. The scope owns some data `D` which
+/// its children may borrow, and resolves to the value returned from its body
+/// once every child has finished. As soon as one child fails the rest are
+/// dropped, which cancels them, and the scope resolves to that error.
+///
+/// Children are polled from within the scope's own `poll`, so they all run on
+/// the task which is waiting on the scope rather than being spawned onto an
+/// executor.
+#[must_use = "futures do nothing unless polled"]
+pub struct Scope<D, T, E> {
+    // Declared before `data` so the children, which may borrow it, are
+    // dropped first.
+    children: Vec<Child<'static, E>>,
+    data: Box<D>,
+    value: Option<T>,
+}
+
+impl<D, T, E> Scope<D, T, E> {
+    /// Creates a new scope owning `data`, running `f` to spawn its children.
+    ///
+    /// The body is run immediately and its return value is what the scope
+    /// resolves to once all of the children it spawned have finished.
+    pub fn new<F>(data: D, f: F) -> Scope<D, T, E>
+        where F: for<'a, 's> FnOnce(&'s Spawner<'a, E>, &'a D) -> T,
+    {
+        let data = Box::new(data);
+        let spawner = Spawner { children: RefCell::new(Vec::new()) };
+        // The data is boxed so this reference stays valid however the scope
+        // is moved around, and `f` can't let it escape anywhere other than
+        // into the children since it must work for any `'a`.
+        let value = f(&spawner, unsafe { &*(&*data as *const D) });
+        let children = spawner.children.into_inner();
+        Scope {
+            children: unsafe { mem::transmute(children) },
+            data: data,
+            value: Some(value),
+        }
+    }
+
+    /// Returns the data owned by this scope.
+    pub fn data(&self) -> &D {
+        &self.data
+    }
+
+    /// Returns how many children haven't finished yet.
+    pub fn pending(&self) -> usize {
+        self.children.len()
+    }
+}
+
+impl<D, T, E> Future for Scope<D, T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T, E> {
+        let mut i = 0;
+        while i < self.children.len() {
+            match self.children[i].poll() {
+                Ok(Async::Ready(())) => drop(self.children.swap_remove(i)),
+                Ok(Async::NotReady) => i += 1,
+                Err(e) => {
+                    self.children.clear();
+                    self.value.take();
+                    return Err(e)
+                }
+            }
+        }
+        if !self.children.is_empty() {
+            return Ok(Async::NotReady)
+        }
+        match self.value.take() {
+            Some(value) => Ok(Async::Ready(value)),
+            None => panic!("cannot poll Scope twice"),
+        }
+    }
+}
+
+impl<D: fmt::Debug, T, E> fmt::Debug for Scope<D, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("data", &self.data)
+            .field("pending", &self.children.len())
+            .finish()
+    }
+}
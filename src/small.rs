@@ -0,0 +1,106 @@
+use std::mem;
+use std::ptr;
+
+use futures::{Future, Poll};
+
+/// A type-erased future which stores small futures inline.
+///
+/// This is an alternative to `Box<Future>` for when the concrete type of a
+/// future needs to be erased, for example when returning futures from trait
+/// methods. Futures whose size and alignment fit within the storage buffer `S`
+/// are stored inline without allocating, and larger futures fall back to a
+/// heap allocation.
+///
+/// The storage buffer is expressed as an array of integers, for example the
+/// default of `[u64; 32]` provides 256 bytes of inline storage. This is the
+/// type returned from functions tagged with `#[async(inline = N)]`.
+///
+/// Only the size and alignment of `S` matter, a value of it is never created.
+pub struct SmallFuture<T, E, S: Copy = [u64; 32]> {
+    inner: Inner<T, E, S>,
+}
+
+/// Space for a future with the layout of `S`, which is never read as an `S`.
+///
+/// Like `MaybeUninit`, the `uninit` variant means any bit pattern is valid
+/// here, including ones which would be invalid for `S` itself.
+#[allow(dead_code)]
+union Storage<S: Copy> {
+    uninit: (),
+    buf: S,
+}
+
+enum Inner<T, E, S: Copy> {
+    Inline {
+        storage: Storage<S>,
+        poll: unsafe fn(*mut u8) -> Poll<T, E>,
+        drop: unsafe fn(*mut u8),
+    },
+    Boxed(Box<Future<Item = T, Error = E>>),
+}
+
+impl<T, E, S: Copy> SmallFuture<T, E, S> {
+    /// Creates a new `SmallFuture`, storing `future` inline if it fits.
+    pub fn new<F>(future: F) -> SmallFuture<T, E, S>
+        where F: Future<Item = T, Error = E> + 'static,
+    {
+        if mem::size_of::<F>() > mem::size_of::<S>() ||
+            mem::align_of::<F>() > mem::align_of::<S>()
+        {
+            return SmallFuture { inner: Inner::Boxed(Box::new(future)) }
+        }
+
+        unsafe {
+            let mut storage = Storage { uninit: () };
+            ptr::write(&mut storage as *mut Storage<S> as *mut F, future);
+            SmallFuture {
+                inner: Inner::Inline {
+                    storage: storage,
+                    poll: poll_inline::<F>,
+                    drop: drop_inline::<F>,
+                },
+            }
+        }
+    }
+
+    /// Returns whether the underlying future is stored inline, or whether it
+    /// was too large and had to be allocated on the heap.
+    pub fn is_inline(&self) -> bool {
+        match self.inner {
+            Inner::Inline { .. } => true,
+            Inner::Boxed(_) => false,
+        }
+    }
+}
+
+unsafe fn poll_inline<F: Future>(ptr: *mut u8) -> Poll<F::Item, F::Error> {
+    (*(ptr as *mut F)).poll()
+}
+
+unsafe fn drop_inline<F>(ptr: *mut u8) {
+    ptr::drop_in_place(ptr as *mut F)
+}
+
+impl<T, E, S: Copy> Future for SmallFuture<T, E, S> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T, E> {
+        match self.inner {
+            Inner::Inline { ref mut storage, poll, .. } => unsafe {
+                poll(storage as *mut Storage<S> as *mut u8)
+            },
+            Inner::Boxed(ref mut future) => future.poll(),
+        }
+    }
+}
+
+impl<T, E, S: Copy> Drop for SmallFuture<T, E, S> {
+    fn drop(&mut self) {
+        if let Inner::Inline { ref mut storage, drop, .. } = self.inner {
+            unsafe {
+                drop(storage as *mut Storage<S> as *mut u8);
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Checking that async code behaves the same however its futures are polled.
+//!
+//! Futures which happen to be ready the first time they're polled can hide
+//! bugs in the code waiting on them, as the paths taken when a future isn't
+//! ready yet are never exercised. The functions in this module run a future
+//! many times, each time with a different *schedule* deciding which of the
+//! `await!`s it reaches return `NotReady` once before actually polling the
+//! future they're waiting on. The result must be the same under every
+//! schedule as when nothing is injected, otherwise the test panics with the
+//! schedule which caused the difference.
+//!
+//! Schedules are written as a string of `0`s and `1`s, one for each `await!`
+//! reached in order, where `1` means `NotReady` was injected there. A failing
+//! schedule can be run again with `replay` to debug it.
+//!
+//! The futures are run with `executor::block_on`, so anything they spawn with
+//! `spawn_local` is subject to the same schedule.
+//!
+//! This module is only available with the `testing` feature of this crate.
+
+use std::cell::RefCell;
+use std::fmt::{Debug, Write};
+
+use futures::Future;
+
+use executor;
+
+/// The most schedules `exhaustive` tries before giving up.
+pub const MAX_SCHEDULES: usize = 4096;
+
+type Site = Option<(&'static str, u32)>;
+
+thread_local!(static SCHEDULE: RefCell<Option<Schedule>> = RefCell::new(None));
+
+struct Schedule {
+    /// Decisions for the first await points reached.
+    prefix: Vec<bool>,
+    /// State of the random number generator making the rest of the decisions,
+    /// if there is one, otherwise nothing else is injected.
+    rng: Option<u64>,
+    /// Every decision made so far, along with where it was made.
+    taken: Vec<(bool, Site)>,
+}
+
+/// Decides whether the `await!` at `site` returns `NotReady` before polling
+/// the future it's waiting on.
+#[doc(hidden)]
+pub fn inject(site: Site) -> bool {
+    SCHEDULE.with(|schedule| {
+        let mut schedule = schedule.borrow_mut();
+        let schedule = match *schedule {
+            Some(ref mut schedule) => schedule,
+            None => return false,
+        };
+        let i = schedule.taken.len();
+        let inject = if i < schedule.prefix.len() {
+            schedule.prefix[i]
+        } else if let Some(ref mut state) = schedule.rng {
+            next_random(state) & 1 == 1
+        } else {
+            false
+        };
+        schedule.taken.push((inject, site));
+        inject
+    })
+}
+
+/// Runs the future created by `make` under every possible schedule, panicking
+/// if any of them changes its result.
+///
+/// The number of schedules grows exponentially with the number of `await!`s
+/// reached, so this stops after `MAX_SCHEDULES` of them.
+pub fn exhaustive<F, T>(mut make: F)
+    where F: FnMut() -> T,
+          T: Future,
+          T::Item: PartialEq + Debug,
+          T::Error: PartialEq + Debug,
+{
+    let (expected, mut taken) = run(make(), Vec::new(), None);
+    for _ in 1..MAX_SCHEDULES {
+        // Treat the decisions taken as a binary number to count up through
+        // every combination of them.
+        let next = match taken.iter().rposition(|&(inject, _)| !inject) {
+            Some(i) => i,
+            None => return,
+        };
+        let mut prefix = taken[..next].iter().map(|&(inject, _)| inject).collect::<Vec<_>>();
+        prefix.push(true);
+        let (actual, now_taken) = run(make(), prefix, None);
+        check(&expected, &actual, &now_taken, None);
+        taken = now_taken;
+    }
+}
+
+/// Runs the future created by `make` under `runs` pseudo-random schedules,
+/// panicking if any of them changes its result.
+///
+/// The schedules are derived from `seed`, so the same seed always tries the
+/// same schedules.
+pub fn seeded<F, T>(seed: u64, runs: usize, mut make: F)
+    where F: FnMut() -> T,
+          T: Future,
+          T::Item: PartialEq + Debug,
+          T::Error: PartialEq + Debug,
+{
+    let (expected, _) = run(make(), Vec::new(), None);
+    for i in 0..runs as u64 {
+        let seed = seed.wrapping_add(i);
+        let (actual, taken) = run(make(), Vec::new(), Some(seed));
+        check(&expected, &actual, &taken, Some(seed));
+    }
+}
+
+/// Runs the future created by `make` under a single schedule, such as one
+/// reported by `exhaustive` or `seeded`, and returns its result.
+///
+/// # Panics
+///
+/// Panics if `schedule` contains anything other than `0`s and `1`s.
+pub fn replay<F, T>(schedule: &str, make: F) -> Result<T::Item, T::Error>
+    where F: FnOnce() -> T,
+          T: Future,
+{
+    let prefix = schedule.chars().map(|c| match c {
+        '0' => false,
+        '1' => true,
+        _ => panic!("invalid schedule \"{}\", expected only `0`s and `1`s", schedule),
+    }).collect();
+    run(make(), prefix, None).0
+}
+
+fn run<T: Future>(future: T, prefix: Vec<bool>, rng: Option<u64>)
+    -> (Result<T::Item, T::Error>, Vec<(bool, Site)>)
+{
+    struct Reset;
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            SCHEDULE.with(|schedule| schedule.borrow_mut().take());
+        }
+    }
+
+    SCHEDULE.with(|schedule| {
+        *schedule.borrow_mut() = Some(Schedule {
+            prefix: prefix,
+            rng: rng,
+            taken: Vec::new(),
+        });
+    });
+    let _reset = Reset;
+    let result = executor::block_on(future);
+    let taken = SCHEDULE.with(|schedule| schedule.borrow_mut().take().unwrap().taken);
+    (result, taken)
+}
+
+fn check<T, E>(expected: &Result<T, E>,
+               actual: &Result<T, E>,
+               taken: &[(bool, Site)],
+               seed: Option<u64>)
+    where T: PartialEq + Debug,
+          E: PartialEq + Debug,
+{
+    if expected == actual {
+        return
+    }
+    let mut schedule = taken.iter()
+        .map(|&(inject, _)| if inject { '1' } else { '0' })
+        .collect::<String>();
+    let len = schedule.trim_right_matches('0').len();
+    schedule.truncate(len);
+
+    let mut msg = String::new();
+    write!(msg, "result changed when `NotReady` was injected").unwrap();
+    if let Some(seed) = seed {
+        write!(msg, " with seed {}", seed).unwrap();
+    }
+    write!(msg, "\n  expected: {:?}\n    actual: {:?}", expected, actual).unwrap();
+    write!(msg, "\n  schedule: \"{}\"\n  injected at:", schedule).unwrap();
+    for &(inject, site) in taken {
+        if !inject {
+            continue
+        }
+        match site {
+            Some((file, line)) => write!(msg, "\n    {}:{}", file, line).unwrap(),
+            None => write!(msg, "\n    <unknown>").unwrap(),
+        }
+    }
+    panic!("{}", msg)
+}
+
+/// Steps the splitmix64 generator, which copes fine with any seed.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,113 @@
+//! Tracing of the logical call chain of async functions.
+//!
+//! With the `trace` feature enabled every future and stream created by
+//! `#[async]`, `#[async_stream]` and the block macros records its name, along
+//! with the location of the `await!` it's suspended at, while it's being
+//! polled. From inside of a poll, for example in a debugging hook or a
+//! watchdog, `current` then returns the chain of async functions the current
+//! task is executing.
+//!
+//! ```ignore
+//! #[async]
+//! fn inner() -> Result<(), ()> {
+//!     println!("{}", futures_await::trace::current());
+//!     Ok(())
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+
+thread_local! {
+    static STACK: RefCell<Vec<Frame>> = RefCell::new(Vec::new());
+}
+
+/// A single async function in an `AsyncTrace`.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    name: &'static str,
+    site: Option<(&'static str, u32)>,
+}
+
+impl Frame {
+    /// Returns the name of the async function or block, for example
+    /// `#[async] fn foo`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the file of the `await!` this function is waiting at, if it
+    /// has reached one yet.
+    pub fn file(&self) -> Option<&'static str> {
+        self.site.map(|(file, _)| file)
+    }
+
+    /// Returns the line of the `await!` this function is waiting at, if it
+    /// has reached one yet.
+    pub fn line(&self) -> Option<u32> {
+        self.site.map(|(_, line)| line)
+    }
+}
+
+/// The logical call chain of async functions being polled on this thread.
+#[derive(Clone, Debug)]
+pub struct AsyncTrace {
+    frames: Vec<Frame>,
+}
+
+impl AsyncTrace {
+    /// Returns the frames of this trace, innermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+impl fmt::Display for AsyncTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "{:4}: {}", i, frame.name)?;
+            if let Some((file, line)) = frame.site {
+                writeln!(f, "             at {}:{}", file, line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the chain of async functions currently being polled on this
+/// thread, innermost first.
+///
+/// This is empty if called outside of the poll of an async function.
+pub fn current() -> AsyncTrace {
+    let mut frames = STACK.with(|stack| stack.borrow().clone());
+    frames.reverse();
+    AsyncTrace { frames }
+}
+
+/// Pushes a frame for the duration of a poll, saving the location of its
+/// `await!` back into `site` afterwards.
+pub fn enter<'a>(name: &'static str, site: &'a mut Option<(&'static str, u32)>) -> Enter<'a> {
+    STACK.with(|stack| stack.borrow_mut().push(Frame { name, site: *site }));
+    Enter { site }
+}
+
+pub struct Enter<'a> {
+    site: &'a mut Option<(&'static str, u32)>,
+}
+
+impl<'a> Drop for Enter<'a> {
+    fn drop(&mut self) {
+        if let Some(frame) = STACK.with(|stack| stack.borrow_mut().pop()) {
+            *self.site = frame.site;
+        }
+    }
+}
+
+/// Records the location of the `await!` the innermost frame is waiting at.
+pub fn set_site(file: &'static str, line: u32) {
+    STACK.with(|stack| {
+        if let Some(frame) = stack.borrow_mut().last_mut() {
+            frame.site = Some((file, line));
+        }
+    });
+}
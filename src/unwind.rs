@@ -0,0 +1,65 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::panic;
+
+/// The value a panic was started with, caught by an async function.
+///
+/// Functions tagged with `#[async(catch_unwind)]` or
+/// `#[async_stream(catch_unwind)]` catch panics from their bodies and return
+/// them as errors instead of unwinding into whoever is polling them. The
+/// function's error type must implement `From<PanicPayload>` for this.
+pub struct PanicPayload(Box<Any + Send + 'static>);
+
+impl PanicPayload {
+    /// Creates a new `PanicPayload` from a value caught by
+    /// `std::panic::catch_unwind`.
+    pub fn new(payload: Box<Any + Send + 'static>) -> PanicPayload {
+        PanicPayload(payload)
+    }
+
+    /// Returns the panic message, if the panic was started with a string as
+    /// is the case for `panic!` with a message.
+    pub fn message(&self) -> Option<&str> {
+        if let Some(s) = self.0.downcast_ref::<&'static str>() {
+            Some(s)
+        } else if let Some(s) = self.0.downcast_ref::<String>() {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the original payload of the panic.
+    pub fn into_inner(self) -> Box<Any + Send + 'static> {
+        self.0
+    }
+
+    /// Resumes unwinding with the original payload of the panic.
+    pub fn resume_unwind(self) -> ! {
+        panic::resume_unwind(self.0)
+    }
+}
+
+impl fmt::Debug for PanicPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PanicPayload")
+            .field(&self.message().unwrap_or("Box<Any>"))
+            .finish()
+    }
+}
+
+impl fmt::Display for PanicPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message() {
+            Some(msg) => write!(f, "async function panicked: {}", msg),
+            None => f.write_str("async function panicked"),
+        }
+    }
+}
+
+impl Error for PanicPayload {
+    fn description(&self) -> &str {
+        "async function panicked"
+    }
+}
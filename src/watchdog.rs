@@ -0,0 +1,227 @@
+//! Detection of futures which stop being polled.
+//!
+//! A future which is never woken up again, for example because a wakeup was
+//! lost, simply sits there without any indication of what went wrong. Wrapping
+//! it in a `Watchdog` records when it was last polled and which `await!` it's
+//! parked at, and a background thread reports futures which haven't been
+//! polled within their threshold to the handler registered with
+//! `set_handler`. Futures are only watched from their first poll onwards, so
+//! one which was created but hasn't been started yet isn't reported.
+//!
+//! Functions tagged with `#[async(watchdog = "5s")]` are wrapped in a
+//! `Watchdog` automatically.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+
+use fused::FusedFuture;
+
+/// A report of a future which hasn't been polled within its threshold.
+#[derive(Clone, Debug)]
+pub struct Stall {
+    name: &'static str,
+    site: Option<(&'static str, u32)>,
+    elapsed: Duration,
+}
+
+impl Stall {
+    /// Returns the name the stalled future was registered with.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the file of the `await!` the future is parked at, if known.
+    pub fn file(&self) -> Option<&'static str> {
+        self.site.map(|(file, _)| file)
+    }
+
+    /// Returns the line of the `await!` the future is parked at, if known.
+    pub fn line(&self) -> Option<u32> {
+        self.site.map(|(_, line)| line)
+    }
+
+    /// Returns how long it's been since the future was last polled.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` has not been polled for {}.{:03}s",
+               self.name,
+               self.elapsed.as_secs(),
+               self.elapsed.subsec_nanos() / 1_000_000)?;
+        if let Some((file, line)) = self.site {
+            write!(f, ", parked at {}:{}", file, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets the function called with stalled futures.
+///
+/// The handler is called from a background thread, once for each time a
+/// future goes without being polled for longer than its threshold. By default
+/// stalls are printed to stderr.
+pub fn set_handler<F>(handler: F)
+    where F: Fn(&Stall) + Send + Sync + 'static,
+{
+    *monitor().handler.lock().unwrap() = Some(Arc::new(handler));
+}
+
+/// A future which reports when it goes too long without being polled.
+#[must_use = "futures do nothing unless polled"]
+pub struct Watchdog<F> {
+    future: F,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    name: &'static str,
+    threshold: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// When the future was last polled, or `None` if it hasn't been yet.
+    last_poll: Option<Instant>,
+    site: Option<(&'static str, u32)>,
+    reported: bool,
+    done: bool,
+}
+
+impl<F: Future> Watchdog<F> {
+    /// Wraps `future`, reporting it under `name` whenever it goes longer than
+    /// `threshold` without being polled once it has been polled for the first
+    /// time.
+    pub fn new(future: F, name: &'static str, threshold: Duration) -> Watchdog<F> {
+        let shared = Arc::new(Shared {
+            name,
+            threshold,
+            state: Mutex::new(State {
+                last_poll: None,
+                site: None,
+                reported: false,
+                done: false,
+            }),
+        });
+        monitor().watched.lock().unwrap().push(Arc::downgrade(&shared));
+        Watchdog { future, shared }
+    }
+
+    /// Returns a reference to the wrapped future.
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    /// Returns a mutable reference to the wrapped future.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.future
+    }
+}
+
+impl<F: Future> Future for Watchdog<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let site = self.shared.state.lock().unwrap().site;
+        let prev = ::__rt::replace_await_site(site);
+        let res = self.future.poll();
+        let site = ::__rt::replace_await_site(prev);
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.last_poll = Some(Instant::now());
+        state.site = site;
+        state.reported = false;
+        state.done = match res {
+            Ok(Async::NotReady) => false,
+            _ => true,
+        };
+        res
+    }
+}
+
+impl<F: FusedFuture> FusedFuture for Watchdog<F> {
+    fn is_terminated(&self) -> bool {
+        self.future.is_terminated()
+    }
+}
+
+struct Monitor {
+    watched: Mutex<Vec<Weak<Shared>>>,
+    handler: Mutex<Option<Arc<Fn(&Stall) + Send + Sync>>>,
+}
+
+fn monitor() -> &'static Monitor {
+    static INIT: Once = ONCE_INIT;
+    static mut MONITOR: *const Monitor = 0 as *const Monitor;
+
+    unsafe {
+        INIT.call_once(|| {
+            MONITOR = Box::into_raw(Box::new(Monitor {
+                watched: Mutex::new(Vec::new()),
+                handler: Mutex::new(None),
+            }));
+            thread::Builder::new()
+                .name("futures-await-watchdog".to_string())
+                .spawn(run)
+                .expect("failed to spawn watchdog thread");
+        });
+        &*MONITOR
+    }
+}
+
+fn run() {
+    let monitor = monitor();
+    loop {
+        let mut stalls = Vec::new();
+        let mut interval = Duration::from_millis(100);
+        {
+            let mut watched = monitor.watched.lock().unwrap();
+            let now = Instant::now();
+            watched.retain(|shared| {
+                let shared = match shared.upgrade() {
+                    Some(shared) => shared,
+                    None => return false,
+                };
+                let mut state = shared.state.lock().unwrap();
+                if state.done {
+                    return false
+                }
+                let last_poll = match state.last_poll {
+                    Some(last_poll) => last_poll,
+                    None => return true,
+                };
+                interval = interval.min(shared.threshold / 2);
+                let elapsed = now.duration_since(last_poll);
+                if !state.reported && elapsed >= shared.threshold {
+                    state.reported = true;
+                    stalls.push(Stall {
+                        name: shared.name,
+                        site: state.site,
+                        elapsed,
+                    });
+                }
+                true
+            });
+        }
+
+        if !stalls.is_empty() {
+            let handler = monitor.handler.lock().unwrap().clone();
+            for stall in stalls {
+                match handler {
+                    Some(ref handler) => handler(&stall),
+                    None => eprintln!("warning: {}", stall),
+                }
+            }
+        }
+
+        thread::sleep(interval.max(Duration::from_millis(1)));
+    }
+}
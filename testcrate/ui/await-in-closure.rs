@@ -0,0 +1,18 @@
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+#[async]
+fn foo() -> Result<i32, i32> {
+    Ok(1)
+}
+
+#[async]
+fn bar() -> Result<i32, i32> {
+    let f = || await!(foo());
+    f()
+}
+
+fn main() {}
@@ -0,0 +1,30 @@
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+macro_rules! try_await {
+    ($e:expr) => (await!($e)?)
+}
+
+#[async]
+fn foo() -> Result<i32, i32> {
+    Ok(1)
+}
+
+// Macros which expand to `await!` work just like writing it directly
+#[async]
+fn bar() -> Result<i32, i32> {
+    let a = try_await!(foo());
+    Ok(a + try_await!(foo()))
+}
+
+// Misuse written directly in the body is still reported
+#[async]
+fn baz() -> Result<i32, i32> {
+    stream_yield!(try_await!(foo()));
+    Ok(1)
+}
+
+fn main() {}
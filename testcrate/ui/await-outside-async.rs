@@ -0,0 +1,12 @@
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+fn foo() -> Result<i32, i32> {
+    let a = await!(futures::future::ok::<i32, i32>(1))?;
+    Ok(a)
+}
+
+fn main() {}
@@ -0,0 +1,37 @@
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use std::thread;
+use std::time::Duration;
+
+use futures::prelude::*;
+
+#[async]
+fn foo() -> Result<i32, i32> {
+    Ok(1)
+}
+
+#[async]
+fn bar() -> Result<i32, i32> {
+    thread::sleep(Duration::from_millis(10));
+    #[allow_blocking]
+    thread::sleep(Duration::from_millis(10));
+    let a = foo().wait()?;
+    let b = await!(foo())?;
+    Ok(a + b)
+}
+
+fn fetch_sync() -> Result<i32, i32> {
+    Ok(1)
+}
+
+#[async(blocking_calls = "self::fetch_sync, .block_on")]
+fn baz() -> Result<i32, i32> {
+    self::fetch_sync()
+}
+
+fn main() {
+    #[allow_blocking]
+    let _ = foo().wait();
+}
@@ -0,0 +1,36 @@
+#![allow(warnings)]
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+#[async]
+fn process(line: String) -> Result<(), u32> {
+    Ok(())
+}
+
+#[async]
+fn lines(string: String) -> Result<(), u32> {
+    for line in string.lines() {
+        await!(process(line.to_string()))?;
+    }
+    Ok(())
+}
+
+#[async]
+fn reference(v: Vec<u32>) -> Result<usize, u32> {
+    let r = &v;
+    await!(process(String::new()))?;
+    Ok(r.len())
+}
+
+// Borrowing through a reference doesn't borrow the local itself
+#[async]
+fn through_reference(s: &'static [u8]) -> Result<usize, u32> {
+    let r = &s[0..1];
+    await!(process(String::new()))?;
+    Ok(r.len())
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+#[async]
+fn foo() -> Result<i32, i32> {
+    stream_yield!(1);
+    Ok(1)
+}
+
+fn main() {}
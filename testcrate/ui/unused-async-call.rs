@@ -0,0 +1,21 @@
+#![deny(unused_must_use)]
+#![feature(proc_macro, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+
+#[async]
+fn foo() -> Result<i32, i32> {
+    Ok(1)
+}
+
+#[async]
+fn bar() -> Result<i32, i32> {
+    foo();
+    await!(foo())
+}
+
+fn main() {
+    foo();
+}
@@ -0,0 +1,98 @@
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::{self, LocalPool};
+use futures::prelude::*;
+use futures::sync::oneshot;
+
+#[async]
+fn push(log: Rc<RefCell<Vec<u32>>>, n: u32) -> Result<(), ()> {
+    yield_now!();
+    log.borrow_mut().push(n);
+    Ok(())
+}
+
+#[async]
+fn spawn_children(log: Rc<RefCell<Vec<u32>>>) -> Result<usize, ()> {
+    for n in 0..3 {
+        executor::spawn_local(push(log.clone(), n));
+    }
+    yield_now!();
+    yield_now!();
+    Ok(log.borrow().len())
+}
+
+#[test]
+fn block_on_runs_spawned_futures() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(executor::block_on(spawn_children(log.clone())), Ok(3));
+    assert_eq!(*log.borrow(), [0, 1, 2]);
+}
+
+#[test]
+fn run_until_idle() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut pool = LocalPool::new();
+    let (tx, rx) = oneshot::channel::<u32>();
+    let log2 = log.clone();
+    pool.spawn_local(async_block! {
+        let n = await!(rx).map_err(|_| ())?;
+        await!(push(log2, n))
+    });
+    pool.spawn_local(push(log.clone(), 1));
+
+    pool.run_until_idle();
+    assert_eq!(*log.borrow(), [1]);
+
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        tx.send(2).unwrap();
+    });
+    pool.run();
+    assert_eq!(*log.borrow(), [1, 2]);
+}
+
+#[test]
+fn block_on_timeout() {
+    let mut pool = LocalPool::new();
+    let result = pool.block_on_timeout(futures::future::empty::<(), ()>(),
+                                       Duration::from_millis(10));
+    assert!(result.is_none());
+}
+
+#[async]
+fn spin() -> Result<(), ()> {
+    loop {
+        yield_now!();
+    }
+}
+
+#[test]
+fn block_on_timeout_self_notifying() {
+    let mut pool = LocalPool::new();
+    let result = pool.block_on_timeout(spin(), Duration::from_millis(10));
+    assert!(result.is_none());
+}
+
+#[test]
+fn run_after_caught_panic() {
+    let mut pool = LocalPool::new();
+    pool.spawn_local(futures::future::lazy(|| -> Result<(), ()> { panic!("boom") }));
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| pool.run())).is_err());
+    // The panicked future is gone, so this returns instead of waiting on it
+    assert_eq!(format!("{:?}", pool), "LocalPool { tasks: 0 }");
+    pool.run();
+}
+
+#[test]
+#[should_panic(expected = "outside of a local executor")]
+fn spawn_local_outside_executor() {
+    executor::spawn_local(futures::future::ok(()));
+}
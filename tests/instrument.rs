@@ -0,0 +1,66 @@
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::{set_instrument, Instrument};
+
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Instrument for Recorder {
+    fn on_first_poll(&self, name: &'static str) {
+        self.0.lock().unwrap().push(format!("first_poll {}", name));
+    }
+
+    fn on_poll(&self, name: &'static str, _duration: Duration) {
+        self.0.lock().unwrap().push(format!("poll {}", name));
+    }
+
+    fn on_yield(&self, name: &'static str) {
+        self.0.lock().unwrap().push(format!("yield {}", name));
+    }
+
+    fn on_complete(&self, name: &'static str) {
+        self.0.lock().unwrap().push(format!("complete {}", name));
+    }
+}
+
+#[async(instrument)]
+fn instrumented() -> Result<i32, ()> {
+    await!(uninstrumented())
+}
+
+#[async]
+fn uninstrumented() -> Result<i32, ()> {
+    Ok(1)
+}
+
+#[async_stream(item = i32, instrument)]
+fn instrumented_stream() -> Result<(), ()> {
+    stream_yield!(1);
+    Ok(())
+}
+
+#[test]
+fn reports_polls() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    set_instrument(Recorder(events.clone())).unwrap();
+    assert!(set_instrument(Recorder(events.clone())).is_err());
+
+    assert_eq!(instrumented().wait(), Ok(1));
+    assert_eq!(instrumented_stream().collect().wait(), Ok(vec![1]));
+
+    assert_eq!(*events.lock().unwrap(), [
+        "first_poll #[async] fn instrumented",
+        "poll #[async] fn instrumented",
+        "complete #[async] fn instrumented",
+        "first_poll #[async_stream] fn instrumented_stream",
+        "poll #[async_stream] fn instrumented_stream",
+        "yield #[async_stream] fn instrumented_stream",
+        "poll #[async_stream] fn instrumented_stream",
+        "complete #[async_stream] fn instrumented_stream",
+    ]);
+}
@@ -0,0 +1,97 @@
+//! Tests for the `mock` feature, run with `cargo test --features mock`.
+
+#![cfg(feature = "mock")]
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+extern crate tokio_io;
+
+use std::io::{self, BufReader};
+use std::time::Duration;
+
+use futures::mock::{self, MockRuntime};
+use futures::prelude::*;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+#[async]
+fn retry(attempts: u32) -> Result<u32, ()> {
+    for attempt in 1..attempts + 1 {
+        let answer = futures::future::empty::<u32, ()>();
+        if let Some(n) = await!(mock::timeout(answer, Duration::from_secs(10)))? {
+            return Ok(n)
+        }
+        await!(mock::sleep(Duration::from_secs(attempt)))?;
+    }
+    Err(())
+}
+
+#[test]
+fn timeouts_use_virtual_time() {
+    let mut rt = MockRuntime::new();
+    assert_eq!(rt.block_on(retry(3)), Err(()));
+    assert_eq!(rt.now(), Duration::from_secs(10 + 1 + 10 + 2 + 10 + 3));
+}
+
+#[test]
+fn sleep_polled_spuriously() {
+    let mut rt = MockRuntime::new();
+    let mut sleep = mock::sleep(Duration::from_secs(1));
+    let mut polls = 0;
+    let f = futures::future::poll_fn(move || {
+        polls += 1;
+        let ready = sleep.poll()?;
+        if polls < 5 {
+            futures::task::current().notify();
+        }
+        Ok::<_, ()>(ready.map(|()| polls))
+    });
+    assert_eq!(rt.block_on(f), Ok(6));
+    assert_eq!(rt.now(), Duration::from_secs(1));
+}
+
+#[test]
+fn dropped_timeout_forgets_its_timer() {
+    let mut rt = MockRuntime::new();
+    let quick = mock::timeout(mock::sleep(Duration::from_secs(1)), Duration::from_secs(60));
+    assert_eq!(rt.block_on(quick), Ok(Some(())));
+    rt.run();
+    assert_eq!(rt.now(), Duration::from_secs(1));
+}
+
+#[test]
+#[should_panic(expected = "can never complete")]
+fn idle_without_timers() {
+    MockRuntime::new().block_on(futures::future::empty::<(), ()>()).unwrap();
+}
+
+// Like `handle_client` in `examples/echo.rs`, but for any stream
+#[async]
+fn echo<S: AsyncRead + AsyncWrite + 'static>(socket: S) -> io::Result<u64> {
+    let (reader, mut writer) = socket.split();
+    let mut total = 0;
+
+    #[async]
+    for line in tokio_io::io::lines(BufReader::new(reader)) {
+        total += line.len() as u64;
+        writer = await!(tokio_io::io::write_all(writer, line))?.0;
+    }
+
+    Ok(total)
+}
+
+#[async]
+fn client<S: AsyncRead + AsyncWrite + 'static>(socket: S) -> io::Result<Vec<u8>> {
+    let (socket, _) = await!(tokio_io::io::write_all(socket, b"hello\nworld\n"))?;
+    // Dropping the socket closes it, ending the echo server's loop
+    let (_, buf) = await!(tokio_io::io::read_exact(socket, [0; 10]))?;
+    Ok(buf.to_vec())
+}
+
+#[test]
+fn duplex_streams() {
+    let mut rt = MockRuntime::new();
+    let (server, socket) = mock::duplex();
+    rt.spawn_local(echo(server).map(|n| assert_eq!(n, 10)).map_err(|e| panic!("{}", e)));
+    assert_eq!(rt.block_on(client(socket)).unwrap(), b"helloworld");
+    rt.run();
+}
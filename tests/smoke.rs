@@ -8,8 +8,13 @@
 extern crate futures_await as futures;
 extern crate futures_cpupool;
 
+use std::cell::Cell;
 use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
 
+use futures::{Cancelled, FusedFuture, FusedStream, PanicPayload, SmallFuture};
 use futures::prelude::*;
 use futures_cpupool::CpuPool;
 
@@ -65,6 +70,11 @@ fn _foo9() -> Result<(), ()> {
     Ok(())
 }
 
+#[async(inline = 256)]
+fn _foo10(a: i32) -> Result<i32, i32> {
+    Ok(a + await!(foo())?)
+}
+
 #[async]
 fn _bar() -> Result<i32, i32> {
     await!(foo())
@@ -238,12 +248,428 @@ fn poll_stream_after_error_stream() -> Result<(), ()> {
 fn poll_stream_after_error() {
     let mut s = poll_stream_after_error_stream();
     assert_eq!(s.poll(), Ok(Async::Ready(Some(5))));
+    assert!(!s.is_terminated());
     assert_eq!(s.poll(), Err(()));
+    assert!(s.is_terminated());
     assert_eq!(s.poll(), Ok(Async::Ready(None)));
 }
 
+#[test]
+fn future_is_terminated() {
+    let mut f = foo();
+    assert!(!f.is_terminated());
+    assert_eq!(f.poll(), Ok(Async::Ready(1)));
+    assert!(f.is_terminated());
+}
+
+#[test]
+#[should_panic(expected = "`#[async] fn foo` polled after completion")]
+fn poll_future_after_completion() {
+    let mut f = foo();
+    assert_eq!(f.poll(), Ok(Async::Ready(1)));
+    drop(f.poll());
+}
+
 #[test]
 fn run_boxed_future_in_cpu_pool() {
     let pool = CpuPool::new_num_cpus();
     pool.spawn(_foo9()).wait().unwrap();
 }
+
+#[test]
+fn inline_future() {
+    let future = _foo10(1);
+    assert!(future.is_inline());
+    assert_eq!(future.wait(), Ok(2));
+
+    let big = futures::future::ok::<_, ()>([0u8; 64]);
+    let future = SmallFuture::<_, _, [u64; 1]>::new(big);
+    assert!(!future.is_inline());
+    assert_eq!(future.wait().map(|a| a.len()), Ok(64));
+
+    // The storage type only provides a layout and is never instantiated
+    let future = SmallFuture::<_, _, &'static u8>::new(futures::future::ok::<u8, ()>(3));
+    assert!(future.is_inline());
+    assert_eq!(future.wait(), Ok(3));
+}
+
+#[derive(Debug, PartialEq)]
+enum CaughtError {
+    Failed,
+    Panicked(String),
+}
+
+impl From<PanicPayload> for CaughtError {
+    fn from(payload: PanicPayload) -> CaughtError {
+        CaughtError::Panicked(payload.message().unwrap_or("").to_string())
+    }
+}
+
+#[async(catch_unwind)]
+fn catch_unwind_future(fail: bool) -> Result<i32, CaughtError> {
+    if fail {
+        panic!("boom");
+    }
+    Err(CaughtError::Failed)
+}
+
+#[async_stream(item = i32, catch_unwind)]
+fn catch_unwind_stream() -> Result<(), CaughtError> {
+    stream_yield!(1);
+    panic!("boom");
+}
+
+#[test]
+fn catch_unwind() {
+    let mut f = catch_unwind_future(true);
+    assert_eq!(f.poll(), Err(CaughtError::Panicked("boom".to_string())));
+    assert!(f.is_terminated());
+
+    assert_eq!(catch_unwind_future(false).wait(), Err(CaughtError::Failed));
+}
+
+#[test]
+fn catch_unwind_in_stream() {
+    let mut s = catch_unwind_stream();
+    assert_eq!(s.poll(), Ok(Async::Ready(Some(1))));
+    assert_eq!(s.poll(), Err(CaughtError::Panicked("boom".to_string())));
+    assert!(s.is_terminated());
+    assert_eq!(s.poll(), Ok(Async::Ready(None)));
+}
+
+#[async(cancellable)]
+fn wait_for_cancel() -> io::Result<i32> {
+    await!(futures::future::empty::<i32, ()>()).unwrap();
+    unreachable!()
+}
+
+#[test]
+fn cancel_future() {
+    let (mut f, handle) = wait_for_cancel();
+    let result = futures::future::lazy(move || {
+        assert_eq!(f.poll().unwrap(), Async::NotReady);
+        handle.cancel();
+        f.poll()
+    }).wait();
+    let e = result.unwrap_err();
+    assert!(e.get_ref().map_or(false, |e| e.is::<Cancelled>()));
+}
+
+#[test]
+fn cancel_from_another_thread() {
+    let (f, handle) = wait_for_cancel();
+    let canceller = thread::spawn(move || handle.cancel());
+    // Whenever the cancellation lands, even before the first `await!` has
+    // registered the task, it must wake the future up
+    let e = f.wait().unwrap_err();
+    assert!(e.get_ref().map_or(false, |e| e.is::<Cancelled>()));
+    canceller.join().unwrap();
+}
+
+#[async(cancellable)]
+fn sum_until_cancelled() -> io::Result<u32> {
+    // Never ready and never wakes the task, so only cancellation can end it
+    let numbers = futures::stream::poll_fn(|| Ok::<_, io::Error>(Async::NotReady));
+    let mut sum = 0;
+    #[async]
+    for n in numbers {
+        sum += n;
+    }
+    Ok(sum)
+}
+
+#[test]
+fn cancel_async_for() {
+    let (f, handle) = sum_until_cancelled();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        handle.cancel();
+    });
+    let e = f.wait().unwrap_err();
+    assert!(e.get_ref().map_or(false, |e| e.is::<Cancelled>()));
+    canceller.join().unwrap();
+}
+
+#[async(cancellable)]
+fn recv_cancellable(rx: futures::sync::oneshot::Receiver<u32>) -> io::Result<u32> {
+    // `oneshot::Canceled` can't be created from `Cancelled`, which is fine as
+    // cancellation is converted into this function's error instead
+    match await!(rx) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(io::Error::new(io::ErrorKind::Other, "closed")),
+    }
+}
+
+#[test]
+fn cancel_awaiting_any_error_type() {
+    let (tx, rx) = futures::sync::oneshot::channel();
+    let (f, _handle) = recv_cancellable(rx);
+    tx.send(3).unwrap();
+    assert_eq!(f.wait().unwrap(), 3);
+
+    let (_tx, rx) = futures::sync::oneshot::channel();
+    let (f, handle) = recv_cancellable(rx);
+    handle.cancel();
+    let e = f.wait().unwrap_err();
+    assert!(e.get_ref().map_or(false, |e| e.is::<Cancelled>()));
+}
+
+#[async]
+fn cancel_after_wait(cancelled: Rc<Cell<bool>>, wait: bool) -> Result<(), ()> {
+    let flag = cancelled.clone();
+    on_cancel!(move || flag.set(true));
+    if wait {
+        await!(futures::future::empty::<(), ()>())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn on_cancel_runs_when_dropped() {
+    let cancelled = Rc::new(Cell::new(false));
+    let mut f = cancel_after_wait(cancelled.clone(), true);
+    assert_eq!(f.poll(), Ok(Async::NotReady));
+    assert!(!cancelled.get());
+    drop(f);
+    assert!(cancelled.get());
+}
+
+#[test]
+fn on_cancel_skipped_on_completion() {
+    let cancelled = Rc::new(Cell::new(false));
+    assert_eq!(cancel_after_wait(cancelled.clone(), false).wait(), Ok(()));
+    assert!(!cancelled.get());
+
+    // Dropping a future which was never polled doesn't run anything either
+    drop(cancel_after_wait(cancelled.clone(), true));
+    assert!(!cancelled.get());
+}
+
+#[async]
+fn yields_once() -> Result<i32, ()> {
+    yield_now!();
+    Ok(1)
+}
+
+#[async(budget = 2)]
+fn sum_with_budget() -> Result<i32, ()> {
+    let mut sum = 0;
+    #[async]
+    for i in futures::stream::iter_ok::<_, ()>(1..5) {
+        sum += i;
+    }
+    Ok(sum)
+}
+
+#[test]
+fn yield_now() {
+    let mut f = yields_once();
+    let result = futures::future::lazy(move || {
+        assert_eq!(f.poll(), Ok(Async::NotReady));
+        f.poll()
+    }).wait();
+    assert_eq!(result, Ok(Async::Ready(1)));
+}
+
+#[test]
+fn budget_forces_yield() {
+    let mut f = sum_with_budget();
+    let polls = futures::future::lazy(move || {
+        let mut polls = 1;
+        while f.poll()? == Async::NotReady {
+            polls += 1;
+        }
+        Ok::<_, ()>(polls)
+    }).wait();
+    assert_eq!(polls, Ok(3));
+    assert_eq!(sum_with_budget().wait(), Ok(10));
+}
+
+#[async]
+fn poll_inline() -> Result<i32, ()> {
+    let mut polls = 0;
+    let n = await_poll!(|| {
+        polls += 1;
+        if polls < 3 {
+            current_task!().notify();
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(polls))
+        }
+    })?;
+    Ok(n)
+}
+
+#[test]
+fn await_poll() {
+    assert_eq!(poll_inline().wait(), Ok(3));
+}
+
+#[async]
+fn blocking_thread() -> Result<Option<String>, ()> {
+    await_blocking!(|| {
+        Ok(thread::current().name().map(|s| s.to_string()))
+    })
+}
+
+#[test]
+fn await_blocking() {
+    let name = blocking_thread().wait().unwrap().unwrap();
+    assert!(name.starts_with("futures-await-blocking-"));
+}
+
+#[async]
+fn scope_sum(xs: Vec<u32>) -> Result<(usize, u32), u32> {
+    let total = Rc::new(Cell::new(0));
+    let spawned = async_scope!(xs, |scope, xs| {
+        for x in xs.iter() {
+            let total = total.clone();
+            scope.spawn(async_block! {
+                yield_now!();
+                if *x > 10 {
+                    return Err(*x)
+                }
+                total.set(total.get() + *x);
+                Ok(())
+            });
+        }
+        xs.len()
+    })?;
+    Ok((spawned, total.get()))
+}
+
+#[async]
+fn scope_fails(cancelled: Rc<Cell<bool>>) -> Result<(), ()> {
+    async_scope!(|scope| {
+        scope.spawn(cancel_after_wait(cancelled.clone(), true));
+        scope.spawn(futures::future::err(()));
+    })
+}
+
+#[test]
+fn async_scope() {
+    assert_eq!(scope_sum(vec![1, 2, 3]).wait(), Ok((3, 6)));
+    assert_eq!(scope_sum(vec![1, 20, 3]).wait(), Err(20));
+
+    let cancelled = Rc::new(Cell::new(false));
+    assert_eq!(scope_fails(cancelled.clone()).wait(), Err(()));
+    assert!(cancelled.get());
+}
+
+#[async_test]
+fn async_test_ok() -> Result<(), u32> {
+    assert_eq!(await!(scope_sum(vec![4, 5]))?, (2, 9));
+    Ok(())
+}
+
+#[async_test(should_panic = "async test failed with error: 20")]
+fn async_test_err() -> Result<(), u32> {
+    await!(scope_sum(vec![1, 20, 3]))?;
+    Ok(())
+}
+
+#[async_test(timeout = "50ms", should_panic = "timed out")]
+fn async_test_timeout() -> Result<(), ()> {
+    await!(futures::future::empty::<(), ()>())
+}
+
+/// Adds one, eventually.
+#[maybe_async]
+fn add_one(n: u32) -> Result<u32, ()> {
+    let n = await!(futures::future::ok::<u32, ()>(n))?;
+    yield_now!();
+    Ok(n + 1)
+}
+
+#[maybe_async(blocking = "double_sync")]
+fn double((a, b): (u32, u32)) -> Result<(u32, u32), ()> {
+    Ok((await!(add_one(a * 2 - 1))?, b * 2))
+}
+
+#[test]
+fn maybe_async() {
+    assert_eq!(add_one(1).wait(), Ok(2));
+    assert_eq!(add_one_blocking(1), Ok(2));
+    assert_eq!(double_sync((1, 2)), Ok((2, 4)));
+}
+
+#[test]
+fn async_closures() {
+    let add = async_closure!(|a: u32, b: u32| -> Result<u32, ()> {
+        let a = await!(futures::future::ok::<u32, ()>(a))?;
+        Ok(a + b)
+    });
+    assert_eq!(add(1, 2).wait(), Ok(3));
+    assert_eq!(add(3, 4).wait(), Ok(7));
+
+    let doubled = futures::stream::iter_ok::<_, ()>(vec![1, 2, 3])
+        .and_then(async_closure!(|n: u32| {
+            yield_now!();
+            Ok(n * 2)
+        }))
+        .collect();
+    assert_eq!(doubled.wait(), Ok(vec![2, 4, 6]));
+
+    let repeat = async_stream_closure!(|n: u32, times: usize| -> Result<(), ()> {
+        for _ in 0..times {
+            stream_yield!(n);
+        }
+        Ok(())
+    });
+    assert_eq!(repeat(5, 3).collect().wait(), Ok(vec![5, 5, 5]));
+}
+
+
+struct Counter {
+    count: u32,
+}
+
+#[async]
+impl Counter {
+    const STEP: u32 = 2;
+
+    fn bump(mut self) -> Result<Counter, ()> {
+        let step = await!(futures::future::ok::<u32, ()>(Counter::STEP))?;
+        self.count += step;
+        Ok(self)
+    }
+
+    fn get(self: Box<Self>) -> Result<u32, ()> {
+        yield_now!();
+        Ok(self.count)
+    }
+
+    #[not_async]
+    fn check(&self) -> Result<u32, ()> {
+        Ok(self.count)
+    }
+
+    fn peek(&self) -> u32 {
+        self.count
+    }
+
+    fn into_count(self) -> io::Result<u32> {
+        yield_now!();
+        Ok(self.count)
+    }
+}
+
+#[async(boxed)]
+impl Counter {
+    fn reset(mut self) -> Result<Counter, ()> {
+        self.count = await!(futures::future::ok::<u32, ()>(0))?;
+        Ok(self)
+    }
+}
+
+#[test]
+fn async_impl() {
+    let counter = Counter { count: 1 }.bump().wait().unwrap();
+    assert_eq!(counter.check(), Ok(3));
+    assert_eq!(counter.peek(), 3);
+    assert_eq!(Box::new(counter).get().wait(), Ok(3));
+    assert_eq!(Counter { count: 4 }.into_count().wait().unwrap(), 4);
+
+    let reset: Box<Future<Item = Counter, Error = ()>> = Counter { count: 5 }.reset();
+    assert_eq!(reset.wait().unwrap().peek(), 0);
+}
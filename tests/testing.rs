@@ -0,0 +1,61 @@
+//! Tests for the `testing` feature, run with `cargo test --features testing`.
+
+#![cfg(feature = "testing")]
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use futures::executor;
+use futures::prelude::*;
+use futures::testing;
+
+#[async]
+fn add(a: u32, b: u32) -> Result<u32, ()> {
+    let a = await!(futures::future::ok::<u32, ()>(a))?;
+    yield_now!();
+    let b = await!(futures::future::ok::<u32, ()>(b))?;
+    Ok(a + b)
+}
+
+// Only sees the spawned increment if it's suspended while waiting
+#[async]
+fn racy(state: Rc<Cell<u32>>) -> Result<u32, ()> {
+    let other = state.clone();
+    executor::spawn_local(futures::future::lazy(move || {
+        other.set(other.get() + 1);
+        Ok(())
+    }));
+    let before = state.get();
+    await!(futures::future::ok::<(), ()>(()))?;
+    Ok(before + state.get())
+}
+
+#[test]
+fn exhaustive_passes() {
+    let mut runs = 0;
+    testing::exhaustive(|| {
+        runs += 1;
+        add(1, 2)
+    });
+    assert_eq!(runs, 4);
+}
+
+#[test]
+fn seeded_passes() {
+    testing::seeded(42, 16, || add(1, 2));
+}
+
+#[test]
+#[should_panic(expected = "schedule: \"1\"")]
+fn exhaustive_finds_race() {
+    testing::exhaustive(|| racy(Rc::new(Cell::new(0))));
+}
+
+#[test]
+fn replay() {
+    assert_eq!(testing::replay("", || racy(Rc::new(Cell::new(0)))), Ok(0));
+    assert_eq!(testing::replay("1", || racy(Rc::new(Cell::new(0)))), Ok(1));
+}
@@ -0,0 +1,33 @@
+//! Tests for the `trace` feature, run with `cargo test --features trace`.
+
+#![cfg(feature = "trace")]
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+
+use futures::prelude::*;
+use futures::trace::{self, AsyncTrace};
+
+#[async]
+fn inner() -> Result<AsyncTrace, ()> {
+    Ok(trace::current())
+}
+
+#[async]
+fn outer() -> Result<AsyncTrace, ()> {
+    await!(inner())
+}
+
+#[test]
+fn call_chain() {
+    let chain = outer().wait().unwrap();
+    let frames = chain.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].name(), "#[async] fn inner");
+    assert_eq!(frames[0].line(), None);
+    assert_eq!(frames[1].name(), "#[async] fn outer");
+    assert_eq!(frames[1].file(), Some("tests/trace.rs"));
+    assert_eq!(frames[1].line(), Some(18));
+
+    assert_eq!(trace::current().frames().len(), 0);
+}
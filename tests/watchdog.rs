@@ -0,0 +1,52 @@
+#![feature(proc_macro, proc_macro_non_items, generators)]
+
+extern crate futures_await as futures;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::watchdog::{self, Stall};
+
+#[async(watchdog = "20ms")]
+fn stuck() -> Result<(), ()> {
+    await!(futures::future::empty::<(), ()>())
+}
+
+#[async(watchdog = "1s")]
+fn quick() -> Result<i32, ()> {
+    Ok(1)
+}
+
+#[test]
+fn reports_stalls() {
+    let stalls = Arc::new(Mutex::new(Vec::new()));
+    let stalls2 = stalls.clone();
+    watchdog::set_handler(move |stall: &Stall| {
+        stalls2.lock().unwrap().push(stall.clone());
+    });
+
+    // Futures aren't watched until they're first polled
+    let unpolled = stuck();
+    thread::sleep(Duration::from_millis(200));
+    assert!(stalls.lock().unwrap().is_empty());
+    drop(unpolled);
+
+    let mut future = stuck();
+    let future = futures::future::lazy(move || {
+        assert_eq!(future.poll(), Ok(Async::NotReady));
+        Ok::<_, ()>(future)
+    }).wait().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let stalls = stalls.lock().unwrap();
+    assert_eq!(stalls.len(), 1);
+    assert_eq!(stalls[0].name(), "#[async] fn stuck");
+    assert_eq!(stalls[0].file(), Some("tests/watchdog.rs"));
+    assert_eq!(stalls[0].line(), Some(14));
+    assert!(stalls[0].elapsed() >= Duration::from_millis(20));
+    drop(future);
+
+    assert_eq!(quick().wait(), Ok(1));
+}